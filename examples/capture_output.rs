@@ -0,0 +1,23 @@
+// You need to have a alpine image @ /root/sandbox/image to run this example
+// The input is fed from memory and the output is captured, nothing touches the disk.
+
+#![feature(box_syntax)]
+
+use ssandbox::{
+    container::{Config, Container, Stdio},
+    filesystem,
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config: Config = Default::default();
+    config.fs.push(box filesystem::MountReadOnlyBindFs::from("/root/sandbox/image".to_string()));
+    config.target_executable = "/bin/cat".to_string();
+    config.stdin = Stdio::Bytes(b"hello from the host\n".to_vec());
+    config.stdout = Stdio::Capture { max_bytes: 1024 };
+    let mut c = Container::from(config);
+    c.start()?;
+    let result = c.wait()?;
+    let output = result.stdout.unwrap_or_default();
+    println!("Captured: {}", String::from_utf8_lossy(&output));
+    Ok(())
+}
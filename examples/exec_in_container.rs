@@ -0,0 +1,30 @@
+// You need to have a alpine image @ /root/sandbox/image to run this example
+// A second process is started inside of an already running container.
+
+#![feature(box_syntax)]
+
+use ssandbox::{
+    container::{Config, Container, ExecSpec},
+    filesystem,
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config: Config = Default::default();
+    config.fs.push(box filesystem::MountProcFs);
+    config.fs.push(box filesystem::MountReadOnlyBindFs::from("/root/sandbox/image".to_string()));
+    config.target_executable = "/bin/sleep".to_string();
+    config.args = vec!["10".to_string()];
+    config.time_limit = std::time::Duration::from_secs(20);
+    let mut c = Container::from(config);
+    c.start()?;
+
+    let mut spec: ExecSpec = Default::default();
+    spec.target_executable = "/bin/ps".to_string();
+    let mut handle = c.exec(spec)?;
+    let result = handle.wait()?;
+    println!("ps finished: {:?}", result);
+
+    c.terminate()?;
+    println!("Finished!");
+    Ok(())
+}
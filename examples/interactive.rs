@@ -0,0 +1,28 @@
+// You need to have a alpine image @ /root/sandbox/image to run this example
+// The interactor asks for the double of a number and rejects a wrong reply.
+
+#![feature(box_syntax)]
+
+use ssandbox::{container::Config, filesystem, interactive::Interaction};
+
+fn config(script: &str) -> Config {
+    let mut config: Config = Default::default();
+    config.fs.push(box filesystem::MountReadOnlyBindFs::from("/root/sandbox/image".to_string()));
+    config.target_executable = "/bin/sh".to_string();
+    config.args = vec!["-c".to_string(), script.to_string()];
+    config
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let solution = config("read x; echo $((x * 2))");
+    let interactor = config("echo 21; read y; [ \"$y\" = 42 ]");
+    let mut interaction = Interaction::new(solution, interactor)?;
+    interaction.start()?;
+    let (solution, interactor) = interaction.wait()?;
+    println!("Solution: {:?}", solution);
+    println!("Interactor: {:?}", interactor);
+    if let Some(verdict) = interaction.judge() {
+        println!("Verdict: {}", verdict.abbreviation());
+    }
+    Ok(())
+}
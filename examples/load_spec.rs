@@ -0,0 +1,63 @@
+// You need to have a alpine image @ /root/sandbox/image to run this example
+// The container is described by a TOML profile instead of Rust code.
+// Run with: cargo run --example load_spec --features spec
+
+use ssandbox::{
+    container::{Config, Container},
+    spec::ConfigSpec,
+};
+
+const PROFILE: &str = r#"
+target_executable = "/bin/sh"
+args = ["-c", "echo $GREETING; ulimit -n"]
+time_limit = 2000
+
+[env]
+GREETING = "hello from a profile"
+
+[[fs]]
+type = "read_only_bind"
+source = "/root/sandbox/image"
+
+[[fs]]
+type = "proc"
+
+[[fs]]
+type = "sized_tmp"
+size_limit = 16777216
+
+[[security_policies]]
+type = "capability"
+
+[[security_policies]]
+type = "seccomp"
+deny = ["ptrace", "mount", "umount2"]
+
+[rlimits]
+open_files = { soft = 64 }
+core_size = { soft = 0, hard = "unlimited" }
+
+[cgroup_limits]
+memory_limit = 67108864
+fork_limit = 16
+
+[stdout]
+type = "capture"
+max_bytes = 1024
+"#;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config: Config = ConfigSpec::from_toml(PROFILE)?.into();
+    let mut c = Container::from(config);
+    c.start()?;
+    let result = c.wait()?;
+    let output = result.stdout.unwrap_or_default();
+    println!("Captured: {}", String::from_utf8_lossy(&output));
+
+    // a mistake in a profile names the field and where it is
+    match ConfigSpec::from_toml("[[fs]]\ntype = \"sized_tmp\"\nsize_limit = \"16M\"\n") {
+        Err(e) => println!("Rejected: {}", e),
+        Ok(_) => unreachable!(),
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+// You need to have a alpine image @ /root/sandbox/image to run this example
+// Checks that no descriptor of the host process leaks into the container.
+
+#![feature(box_syntax)]
+
+use {
+    nix::{
+        fcntl::{self, OFlag},
+        sys::stat::Mode,
+        unistd,
+    },
+    ssandbox::{
+        container::{Config, Container, Stdio},
+        filesystem,
+    },
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // neither opened with O_CLOEXEC nor given to the container
+    let leaked = fcntl::open("/etc/hostname", OFlag::O_RDONLY, Mode::empty())?;
+    unistd::dup2(leaked, 100)?;
+
+    let mut config: Config = Default::default();
+    config.fs.push(box filesystem::MountReadOnlyBindFs::from("/root/sandbox/image".to_string()));
+    config.fs.push(box filesystem::MountProcFs);
+    config.target_executable = "/bin/ls".to_string();
+    config.args = vec!["/proc/self/fd".to_string()];
+    config.stdout = Stdio::Capture { max_bytes: 4096 };
+    let mut c = Container::from(config);
+    c.start()?;
+    let result = c.wait()?;
+
+    // 0, 1 and 2 plus the directory ls is reading
+    let output = String::from_utf8(result.stdout.unwrap_or_default())?;
+    let fds: Vec<&str> = output.split_whitespace().collect();
+    println!("Open in the container: {:?}", fds);
+    assert_eq!(fds, vec!["0", "1", "2", "3"]);
+    Ok(())
+}
@@ -3,7 +3,11 @@
 #![feature(box_syntax)]
 #![feature(type_ascription)]
 
-use ssandbox::{container::{Config, Container}, filesystem};
+use ssandbox::{
+    container::{Config, Container, Stdio},
+    filesystem,
+    security::{Resource, Rlimit, RlimitValue},
+};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut config: Config = Default::default();
@@ -14,11 +18,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     config.fs.push(box filesystem::MountExtraFs::new());
     config.cgroup_limits.set_fork_limit(10);
     config.cgroup_limits.set_memory_limit(512 * 1024 * 1024); // 512Mb
-    config.time_limit = std::time::Duration::from_secs(1);
-    config.stdout = Some("/root/sandbox/io/output.txt".to_string());
+    config.cgroup_limits.set_time_limit(1000); // 1s of cpu time
+    config.time_limit = std::time::Duration::from_secs(3);
+    config.rlimits.set(Resource::StackSize, Rlimit::fixed(RlimitValue::Unlimited));
+    config.rlimits.set(Resource::FileSize, Rlimit::fixed(RlimitValue::Limited(64 * 1024 * 1024)));
+    config.stdout = Stdio::File("/root/sandbox/io/output.txt".to_string());
+    config.output_limit = Some(16 * 1024 * 1024); // 16Mb
     let mut c = Container::from(config);
     c.start()?;
-    c.wait()?;
-    println!("Finished!");
+    let result = c.wait()?;
+    println!("Finished! {:?}", result);
     Ok(())
 }
@@ -4,7 +4,111 @@
 #![feature(box_syntax)]
 #![feature(type_ascription)]
 
-use ssandbox::{container::{Config, Container}, filesystem};
+use {
+    nix::{
+        errno::Errno,
+        libc,
+        poll::{self, PollFd, PollFlags},
+        sys::{
+            signal::{SigSet, Signal},
+            signalfd::SignalFd,
+            termios::{self, SetArg, Termios},
+        },
+        unistd,
+    },
+    ssandbox::{
+        container::{Config, Container, Pty, Stdio},
+        filesystem,
+    },
+    std::{
+        io::{self, Read, Write},
+        os::unix::io::AsRawFd,
+    },
+};
+
+// The terminal of the container does the line editing and echo, ours is raw
+// until this is dropped, also if we return early with an error.
+struct RawMode(Option<Termios>);
+
+impl RawMode {
+    fn enter() -> nix::Result<Self> {
+        // None if stdin is not a terminal
+        let saved = termios::tcgetattr(libc::STDIN_FILENO).ok();
+        if let Some(saved) = &saved {
+            let mut raw = saved.clone();
+            termios::cfmakeraw(&mut raw);
+            termios::tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, &raw)?;
+        }
+        Ok(Self(saved))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.0 {
+            let _ = termios::tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, saved);
+        }
+    }
+}
+
+fn copy_window_size(pty: &Pty) -> ssandbox::Result<()> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 {
+        // the shell inside gets a SIGWINCH
+        pty.resize(size.ws_row, size.ws_col)?;
+    }
+    Ok(())
+}
+
+// Copies between our terminal and the pty, and passes on the size of our
+// window whenever it changes, until the shell is gone.
+fn forward(pty: &Pty) -> Result<(), Box<dyn std::error::Error>> {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGWINCH);
+    mask.thread_block()?;
+    let mut winch = SignalFd::new(&mask)?;
+    copy_window_size(pty)?;
+
+    let mut master = pty.try_clone()?;
+    let mut stdin_open = true;
+    let mut buf = [0; 4096];
+    loop {
+        // a negative fd is skipped by poll(2)
+        let stdin = if stdin_open { libc::STDIN_FILENO } else { -1 };
+        let mut fds = [
+            PollFd::new(stdin, PollFlags::POLLIN),
+            PollFd::new(master.as_raw_fd(), PollFlags::POLLIN),
+            PollFd::new(winch.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        match poll::poll(&mut fds, -1) {
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            x => x?,
+        };
+        let ready = |i: usize| !fds[i].revents().unwrap_or_else(PollFlags::empty).is_empty();
+
+        if ready(2) {
+            winch.read_signal()?;
+            copy_window_size(pty)?;
+        }
+        if ready(1) {
+            // reading the master fails with EIO once the shell is gone
+            match master.read(&mut buf) {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(n) => {
+                    io::stdout().write_all(&buf[..n])?;
+                    io::stdout().flush()?;
+                }
+            }
+        }
+        if ready(0) {
+            // not io::stdin(), its buffer would hide input from poll(2)
+            match unistd::read(libc::STDIN_FILENO, &mut buf)? {
+                0 => stdin_open = false,
+                n => master.write_all(&buf[..n])?,
+            }
+        }
+    }
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut config: Config = Default::default();
@@ -15,9 +119,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     config.cgroup_limits.set_fork_limit(10);
     config.cgroup_limits.set_memory_limit(512 * 1024 * 1024); // 512Mb
     config.time_limit = std::time::Duration::from_secs(200);
+    config.stdin = Stdio::Pty;
+    config.stdout = Stdio::Pty;
+    config.stderr = Stdio::Pty;
     let mut c = Container::from(config);
     c.start()?;
-    c.wait()?;
+
+    {
+        let _raw_mode = RawMode::enter()?;
+        forward(c.pty().unwrap())?;
+        c.wait()?;
+    }
     println!("Finished!");
     Ok(())
 }
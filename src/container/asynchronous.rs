@@ -0,0 +1,218 @@
+use {
+    super::{
+        error, join_stdio, pidfd::PidFd, report, wait_for_exit, Config, Container, EntryFailure,
+        EntryStage, RunResult,
+    },
+    crate::Result,
+    nix::{
+        fcntl::{self, FcntlArg, OFlag},
+        unistd,
+    },
+    std::{
+        fs::File,
+        io,
+        os::unix::io::{AsRawFd, FromRawFd},
+        sync::Arc,
+        time::Instant,
+    },
+    tokio::io::unix::AsyncFd,
+};
+
+#[derive(Debug)]
+pub struct AsyncContainer {
+    inner: Container,
+    kill_on_cancel: bool,
+}
+
+impl std::convert::From<Config> for AsyncContainer {
+    fn from(source: Config) -> Self {
+        Self::from(Container::from(source))
+    }
+}
+
+impl std::convert::From<Container> for AsyncContainer {
+    fn from(source: Container) -> Self {
+        Self {
+            inner: source,
+            kill_on_cancel: true,
+        }
+    }
+}
+
+// Kills the container if a future is dropped before it completes.
+struct CancelGuard {
+    pidfd: Option<Arc<PidFd>>,
+}
+
+impl CancelGuard {
+    fn new(pidfd: &Arc<PidFd>, armed: bool) -> Self {
+        Self {
+            pidfd: if armed { Some(pidfd.clone()) } else { None },
+        }
+    }
+
+    fn disarm(&mut self) {
+        self.pidfd = None;
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(pidfd) = &self.pidfd {
+            let _ = pidfd.kill();
+        }
+    }
+}
+
+impl AsyncContainer {
+    pub fn container(&self) -> &Container {
+        &self.inner
+    }
+
+    pub fn container_mut(&mut self) -> &mut Container {
+        &mut self.inner
+    }
+
+    pub fn set_kill_on_cancel(&mut self, value: bool) -> &mut Self {
+        self.kill_on_cancel = value;
+        self
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        // closed on drop, also if this future is dropped before it completes
+        let report_pipe = unsafe { File::from_raw_fd(self.inner.launch()?) };
+        let pidfd = self.inner.pidfd.clone().unwrap();
+        let mut guard = CancelGuard::new(&pidfd, self.kill_on_cancel);
+
+        let mut last_stage = None;
+        let timeout = self.inner.config.setup_timeout;
+        let report = tokio::time::timeout(timeout, read_report(report_pipe, &mut last_stage));
+        let report = match report.await {
+            Ok(x) => x,
+            Err(_elapsed) => Err(error::Error::SetupTimedOut(last_stage).into()),
+        };
+        if let Err(e) = self.inner.check_report(report) {
+            if self.inner.timed_out() {
+                self.abort_setup().await?;
+            }
+            return Err(e);
+        }
+        guard.disarm();
+
+        self.inner.started_at = Some(Instant::now());
+        let watchdog = self.inner.watchdog();
+        // a duplicate is registered, the same fd can not be added twice
+        let watched = AsyncFd::new(pidfd.try_clone()?)?;
+        tokio::spawn(async move {
+            while let Some(timeout) = watchdog.check() {
+                let exited = tokio::time::timeout(timeout, watched.readable()).await;
+                if exited.is_ok() {
+                    return;
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    // Like Container::abort_setup(), without blocking the runtime.
+    async fn abort_setup(&mut self) -> Result<()> {
+        let pidfd = self.inner.pidfd.clone().unwrap();
+        pidfd.kill()?;
+        let watched = AsyncFd::new(pidfd.try_clone()?)?;
+        let timeout = self.inner.config.setup_timeout;
+        if let Ok(exited) = tokio::time::timeout(timeout, watched.readable()).await {
+            let _ = exited?;
+            self.wait().await?;
+            let config = &self.inner.config;
+            config.cgroup_limits.delete(config.uid)?;
+        }
+        Ok(())
+    }
+
+    pub async fn wait(&mut self) -> Result<RunResult> {
+        if let Some(result) = self.inner.result() {
+            return Ok(result.clone());
+        }
+
+        let (pid, pidfd) = match (self.inner.container_pid, &self.inner.pidfd) {
+            (Some(pid), Some(pidfd)) => (pid, pidfd.clone()),
+            _ => return Err(error::Error::NotStarted.into()),
+        };
+        let mut guard = CancelGuard::new(&pidfd, self.kill_on_cancel);
+
+        // a pidfd becomes readable once the process has exited
+        let watched = AsyncFd::new(pidfd.try_clone()?)?;
+        let _ = watched.readable().await?;
+        guard.disarm();
+        let wall_time = self.inner.wall_time();
+
+        // the pumps may still be draining the pipes, the child is only reaped
+        // afterwards so that a cancelled wait() can be repeated
+        let stdio = self.inner.stdio.clone();
+        let output = tokio::task::spawn_blocking(move || join_stdio(&stdio))
+            .await
+            .map_err(io::Error::from)?;
+
+        // the child is a zombie by now, so this does not block
+        let (status, usage) = wait_for_exit(pid)?;
+        self.inner.already_ended = true;
+        Ok(self.inner.finish(status, &usage, wall_time, output))
+    }
+
+    pub fn terminate(&mut self) -> Result<()> {
+        self.inner.terminate()
+    }
+
+    pub fn delete(&mut self) -> Result<()> {
+        self.inner.delete()
+    }
+}
+
+async fn read_full(fd: &AsyncFd<File>, buf: &mut [u8]) -> Result<usize> {
+    let mut offset = 0;
+    while offset < buf.len() {
+        let mut ready = fd.readable().await?;
+        let res = ready.try_io(|inner| {
+            unistd::read(inner.get_ref().as_raw_fd(), &mut buf[offset..])
+                .map_err(crate::error::io_error)
+        });
+        match res {
+            Ok(Ok(0)) => break,
+            Ok(Ok(n)) => offset += n,
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_would_block) => continue,
+        }
+    }
+    Ok(offset)
+}
+
+// Like report::read_report(), but the timeout is up to the caller, who reads
+// last_stage afterwards.
+async fn read_report(
+    report_pipe: File,
+    last_stage: &mut Option<EntryStage>,
+) -> Result<Option<EntryFailure>> {
+    fcntl::fcntl(
+        report_pipe.as_raw_fd(),
+        FcntlArg::F_SETFL(OFlag::O_NONBLOCK),
+    )?;
+    let fd = AsyncFd::new(report_pipe)?;
+
+    loop {
+        let mut header = [0_u8; report::HEADER_LEN];
+        let n = read_full(&fd, &mut header).await?;
+        if n == 0 {
+            return report::end_of_report(*last_stage);
+        }
+        let (kind, len) = report::parse_header(&header[..n])?;
+        let mut payload = vec![0_u8; len];
+        if read_full(&fd, &mut payload).await? < len {
+            return Err(error::Error::MalformedReport.into());
+        }
+        match report::parse_payload(kind, &payload)? {
+            report::Frame::Stage(stage) => *last_stage = Some(stage),
+            report::Frame::Failure(failure) => return Ok(Some(failure)),
+        }
+    }
+}
@@ -1,20 +1,51 @@
 use {
-    super::Config,
-    crate::{security::ApplySecurityPolicy, CommonResult, VoidResult},
+    super::{
+        error::{self, EntryFailure, EntryStage},
+        report, Config,
+    },
+    crate::{
+        filesystem::RootMode, security::ApplySecurityPolicy, CommonResult, Source, VoidResult,
+    },
     nix::{
-        mount::{self, MsFlags},
+        errno::Errno,
+        fcntl::{self, FcntlArg, OFlag},
+        libc,
+        mount::{self, MntFlags, MsFlags},
+        sched::{self, CloneFlags},
+        sys::stat::Mode,
+        time::{self, ClockId},
         unistd,
     },
-    std::{ffi::CString, fs, os::unix::io::RawFd, sync::Arc},
+    std::{
+        ffi::CString,
+        fs,
+        os::unix::io::RawFd,
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
 };
 
 type NeverResult = CommonResult<!>;
 
+// Tags the error of a step of the setup with the stage it belongs to.
+pub(super) trait AtStage<T> {
+    fn at(self, stage: EntryStage) -> Result<T, EntryFailure>;
+}
+
+impl<T, E: Into<Source>> AtStage<T> for Result<T, E> {
+    fn at(self, stage: EntryStage) -> Result<T, EntryFailure> {
+        self.map_err(|e| EntryFailure::from_error(stage, e.into().as_ref()))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct InternalData {
     pub config: Arc<Config>,
     pub ready_pipe_set: (RawFd, RawFd),
     pub report_pipe_set: (RawFd, RawFd),
+    pub stdio_fds: Vec<(RawFd, RawFd)>, // opened by the parent
+    pub controlling_tty: Option<RawFd>, // one of the targets in stdio_fds
+    pub setgroups_allowed: bool,
 }
 
 fn set_hostname(hostname: &String) -> VoidResult {
@@ -22,6 +53,40 @@ fn set_hostname(hostname: &String) -> VoidResult {
     Ok(())
 }
 
+fn enter_time_namespace() -> VoidResult {
+    // CLONE_NEWTIME can not be passed to clone(2), and unshare(2) only makes
+    // it the namespace of our future children, so we join it afterwards.
+    Errno::result(unsafe { libc::unshare(libc::CLONE_NEWTIME) })?;
+
+    let monotonic = time::clock_gettime(ClockId::CLOCK_MONOTONIC)?;
+    let boottime = time::clock_gettime(ClockId::CLOCK_BOOTTIME)?;
+    fs::write(
+        "/proc/self/timens_offsets",
+        format!(
+            "monotonic {} 0\nboottime {} 0\n",
+            -monotonic.tv_sec(),
+            -boottime.tv_sec()
+        ),
+    )?;
+
+    let fd = fcntl::open(
+        "/proc/self/ns/time_for_children",
+        OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    let res = Errno::result(unsafe { libc::setns(fd, libc::CLONE_NEWTIME) });
+    unistd::close(fd)?;
+    res?;
+    Ok(())
+}
+
+fn enter_cgroup_namespace() -> VoidResult {
+    // Done here rather than at clone time: only now has the parent moved us
+    // into our own cgroup, which then becomes the root of the namespace.
+    sched::unshare(CloneFlags::CLONE_NEWCGROUP)?;
+    Ok(())
+}
+
 fn get_container_workpath(base_path: &String, uid: u64) -> std::path::PathBuf {
     [base_path, &uid.to_string()].iter().collect()
 }
@@ -43,6 +108,29 @@ fn change_rootpath(root: &std::path::Path) -> VoidResult {
     Ok(())
 }
 
+fn pivot_rootpath(root: &std::path::Path) -> VoidResult {
+    // the new root has to be a mount point
+    mount::mount::<_, _, str, str>(
+        Some(root),
+        root,
+        None,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None,
+    )?;
+    unistd::chdir(root)?;
+
+    // stack the old root on top of the new one, see detach_old_root()
+    unistd::pivot_root(".", ".")?;
+    Ok(())
+}
+
+// The working directory is still the new root, the old one is on top of it.
+fn detach_old_root() -> VoidResult {
+    mount::umount2(".", MntFlags::MNT_DETACH)?;
+    unistd::chdir("/")?;
+    Ok(())
+}
+
 fn create_rootdir(root: &std::path::Path) -> VoidResult {
     if root.exists() {
         fs::remove_dir_all(root)?;
@@ -58,123 +146,304 @@ fn mount_filesystem(config: Arc<Config>) -> VoidResult {
     create_rootdir(&container_rootpath)?;
     mark_mount_ns_private()?;
 
-    // mounts before chroot
+    // mounts before switching root
     for x in config.fs.iter() {
         x.loading(&container_rootpath, &container_workpath)?;
     }
 
-    // chroot
-    change_rootpath(&container_rootpath)?;
+    // switch root
+    match config.root_mode {
+        RootMode::PivotRoot => pivot_rootpath(&container_rootpath)?,
+        RootMode::Chroot => change_rootpath(&container_rootpath)?,
+    }
 
-    // mounts after chroot
+    // mounts after switching root, a new procfs is only allowed in a user
+    // namespace while the one of the host is still attached
     for x in config.fs.iter() {
         x.loaded()?;
     }
 
+    if config.root_mode == RootMode::PivotRoot {
+        detach_old_root()?;
+    }
     Ok(())
 }
 
-fn run_init(config: Arc<Config>) -> NeverResult {
-    let cstyle_target = CString::new(config.target_executable.to_string())?;
-    unistd::execve::<_, CString>(&cstyle_target, &[cstyle_target.as_c_str()], &[])?;
+pub(super) fn run_init(
+    target: &str,
+    args: &[String],
+    env: &[(String, String)],
+    executable: &Path,
+) -> NeverResult {
+    let cstyle_target = CString::new(executable.to_string_lossy().into_owned())?;
+
+    let mut cstyle_args = vec![CString::new(target.to_string())?];
+    for arg in args.iter() {
+        cstyle_args.push(CString::new(arg.to_string())?);
+    }
+
+    let mut cstyle_env = Vec::new();
+    for (key, value) in env.iter() {
+        cstyle_env.push(CString::new(format!("{}={}", key, value))?);
+    }
+
+    unistd::execve(&cstyle_target, &cstyle_args, &cstyle_env)?;
 
     unreachable!()
 }
 
-fn block_until_ready(p: RawFd) -> VoidResult {
-    unistd::read(p, &mut Vec::new())?;
+// The parent closes the pipe when it is ready, nothing is ever written.
+pub(super) fn block_until_ready(p: RawFd) -> VoidResult {
+    loop {
+        match unistd::read(p, &mut [0_u8; 1]) {
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            x => {
+                x?;
+                break;
+            }
+        }
+    }
+    // it is kept by close_inherited_fds() until here
+    unistd::close(p)?;
     Ok(())
 }
 
-fn apply_security_policy(policies: &Vec<Box<dyn ApplySecurityPolicy>>) -> VoidResult {
+pub(super) fn apply_security_policy(policies: &Vec<Box<dyn ApplySecurityPolicy>>) -> VoidResult {
     for policy in policies.iter() {
         policy.apply()?;
     }
     Ok(())
 }
 
-fn redirect_standard_io(config: Arc<Config>) -> VoidResult {
-    const STDIN_FN: RawFd = 0;
-    const STDOUT_FN: RawFd = 1;
-    const STDERR_FN: RawFd = 2;
+fn drop_capabilities(sets: &[caps::CapSet]) -> VoidResult {
+    for set in sets.iter() {
+        caps::clear(None, *set)?;
+    }
+    Ok(())
+}
+
+pub(super) fn switch_user(inner_uid: u32, inner_gid: u32, setgroups_allowed: bool) -> VoidResult {
+    use caps::CapSet;
+    let uid = unistd::Uid::from_raw(inner_uid);
+    let gid = unistd::Gid::from_raw(inner_gid);
 
-    use nix::{fcntl::OFlag, sys::stat::Mode};
-    fn open_input(path: std::path::PathBuf) -> CommonResult<RawFd> {
-        Ok(nix::fcntl::open(
-            &path,
-            OFlag::O_RDONLY | OFlag::O_CLOEXEC,
-            Mode::empty(),
-        )?)
+    // clearing the bounding set needs CAP_SETPCAP, which setresuid() takes
+    // away if it changes the uid from 0, e.g. with IdMapping::from_subid()
+    if inner_uid != 0 {
+        drop_capabilities(&[CapSet::Bounding])?;
     }
+    drop_capabilities(&[CapSet::Ambient])?;
 
-    fn open_output(path: std::path::PathBuf) -> CommonResult<RawFd> {
-        Ok(nix::fcntl::open(
-            &path,
-            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC | OFlag::O_CLOEXEC,
-            Mode::from_bits_truncate(0o644),
-        )?)
+    // setgroups(2) always fails once "deny" was written for this namespace
+    if setgroups_allowed {
+        unistd::setgroups(&[])?;
     }
+    unistd::setresgid(gid, gid, gid)?;
+    unistd::setresuid(uid, uid, uid)?;
 
-    fn do_redirect(source: RawFd, target: RawFd) -> VoidResult {
-        unistd::dup2(source, target)?;
-        unistd::close(source)?;
-        Ok(())
+    // If the uid has not changed, e.g. with a single id mapping, we keep every
+    // capability of the new user namespace, so they are dropped explicitly.
+    // The root of the container keeps them, CapabilityPolicy limits them.
+    if inner_uid != 0 {
+        drop_capabilities(&[CapSet::Inheritable, CapSet::Effective, CapSet::Permitted])?;
     }
+    Ok(())
+}
 
-    if let Some(p) = &config.stdin {
-        let fd = open_input(std::path::PathBuf::from(p))?;
-        do_redirect(fd, STDIN_FN)?;
+pub(super) fn redirect_standard_io(fds: &[(RawFd, RawFd)]) -> VoidResult {
+    // our copies are numbered above every target, see StdioSetup
+    for (source, target) in fds.iter() {
+        unistd::dup2(*source, *target)?;
+        unistd::close(*source)?;
     }
+    Ok(())
+}
 
-    if let Some(p) = &config.stdout {
-        let fd = open_output(std::path::PathBuf::from(p))?;
-        do_redirect(fd, STDOUT_FN)?;
+// The slave of a pty becomes the terminal of a new session, so job control
+// and the signals of the terminal only reach the container.
+pub(super) fn set_controlling_tty(fd: RawFd) -> VoidResult {
+    unistd::setsid()?;
+    Errno::result(unsafe { libc::ioctl(fd, libc::TIOCSCTTY, 0) })?;
+    Ok(())
+}
+
+fn close_range(first: RawFd, last: RawFd, flags: libc::c_uint) -> nix::Result<()> {
+    Errno::result(unsafe {
+        libc::syscall(
+            libc::SYS_close_range,
+            first as libc::c_uint,
+            last as libc::c_uint,
+            flags,
+        )
+    })?;
+    Ok(())
+}
+
+// Closes every fd above stdio which is not kept. Done before the root is
+// switched, the fallback needs /proc, and before seccomp may forbid it.
+pub(super) fn close_inherited_fds(keep: &[RawFd]) -> VoidResult {
+    let mut keep: Vec<RawFd> = keep.iter().copied().filter(|x| *x > 2).collect();
+    keep.sort_unstable();
+
+    let mut first = 3;
+    let mut ranges = Vec::new();
+    for fd in keep.iter() {
+        if first < *fd {
+            ranges.push((first, *fd - 1));
+        }
+        first = *fd + 1;
     }
+    ranges.push((first, RawFd::MAX));
 
-    if let Some(p) = &config.stderr {
-        let fd = open_output(std::path::PathBuf::from(p))?;
-        do_redirect(fd, STDERR_FN)?;
+    match ranges
+        .iter()
+        .try_for_each(|(first, last)| close_range(*first, *last, 0))
+    {
+        Ok(_) => return Ok(()),
+        // close_range(2) needs linux 5.9
+        Err(nix::Error::Sys(Errno::ENOSYS)) => {}
+        Err(e) => return Err(box e),
     }
 
+    // collected first, closing the fd of read_dir would end the iteration
+    let mut found = Vec::new();
+    for entry in fs::read_dir("/proc/self/fd")? {
+        found.push(entry?.file_name().to_string_lossy().parse::<RawFd>()?);
+    }
+    for fd in found.into_iter().filter(|x| *x > 2 && !keep.contains(x)) {
+        // the fd of read_dir itself is already closed
+        let _ = unistd::close(fd);
+    }
     Ok(())
 }
 
-fn check_init(config: Arc<Config>) -> VoidResult {
-    unistd::access::<str>(&config.target_executable, unistd::AccessFlags::X_OK)?;
+pub(super) fn resolve_executable(
+    target: &str,
+    env: &[(String, String)],
+    search_path: bool,
+) -> CommonResult<PathBuf> {
+    const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+    if !search_path || target.contains('/') {
+        return Ok(PathBuf::from(target));
+    }
+
+    let search_path = env
+        .iter()
+        .find(|(key, _)| key == "PATH")
+        .map(|(_, value)| value.as_str())
+        .unwrap_or(DEFAULT_PATH);
+
+    for dir in search_path.split(':').filter(|x| !x.is_empty()) {
+        let candidate = Path::new(dir).join(target);
+        if candidate.is_file() && unistd::access(&candidate, unistd::AccessFlags::X_OK).is_ok() {
+            return Ok(candidate);
+        }
+    }
+
+    Err(box error::Error::ExecutableNotFound(target.to_string()))
+}
+
+pub(super) fn check_init(executable: &Path) -> VoidResult {
+    unistd::access(executable, unistd::AccessFlags::X_OK)?;
     Ok(())
 }
 
-fn exceptable_main(config: Arc<Config>, ready_pipe: RawFd, report_pipe: RawFd) -> NeverResult {
-    set_hostname(&config.hostname)?;
-    redirect_standard_io(config.clone())?;
-    mount_filesystem(config.clone())?;
-    apply_security_policy(&config.security_policies)?;
-    check_init(config.clone())?;
+// Announces the stage we are about to enter, the parent names it if we hang.
+pub(super) fn enter(report_pipe: RawFd, stage: EntryStage) -> Result<(), EntryFailure> {
+    report::write_stage(report_pipe, stage).at(stage)
+}
+
+fn exceptable_main(
+    config: Arc<Config>,
+    ready_pipe: RawFd,
+    report_pipe: RawFd,
+    stdio_fds: Vec<(RawFd, RawFd)>,
+    controlling_tty: Option<RawFd>,
+    setgroups_allowed: bool,
+) -> Result<!, EntryFailure> {
+    enter(report_pipe, EntryStage::Hostname)?;
+    set_hostname(&config.hostname).at(EntryStage::Hostname)?;
+    if config.time_namespace {
+        // /proc still refers to the procfs of the host here
+        enter_time_namespace().at(EntryStage::Hostname)?;
+    }
+    enter(report_pipe, EntryStage::Stdio)?;
+    redirect_standard_io(&stdio_fds).at(EntryStage::Stdio)?;
+    if let Some(fd) = controlling_tty {
+        set_controlling_tty(fd).at(EntryStage::Stdio)?;
+    }
+    // the cloned child has started with the whole fd table of the host process,
+    // the report pipe is closed by a successful execve(2)
+    let mut keep_fds: Vec<RawFd> = stdio_fds.iter().map(|x| x.1).collect();
+    keep_fds.extend_from_slice(&[ready_pipe, report_pipe]);
+    close_inherited_fds(&keep_fds).at(EntryStage::Stdio)?;
+    enter(report_pipe, EntryStage::Mount)?;
+    mount_filesystem(config.clone()).at(EntryStage::Mount)?;
 
-    block_until_ready(ready_pipe)?;
-    unistd::write(report_pipe, &[0])?;
-    run_init(config)
+    // uid_map and gid_map are written by the parent before it is ready
+    enter(report_pipe, EntryStage::Policy)?;
+    block_until_ready(ready_pipe).at(EntryStage::Policy)?;
+    enter_cgroup_namespace().at(EntryStage::Policy)?;
+    config.network.setup_inside().at(EntryStage::Policy)?;
+    config.rlimits.apply().at(EntryStage::Policy)?;
+    switch_user(config.inner_uid, config.inner_gid, setgroups_allowed).at(EntryStage::Policy)?;
+    apply_security_policy(&config.security_policies).at(EntryStage::Policy)?;
+    enter(report_pipe, EntryStage::CheckInit)?;
+    let executable = resolve_executable(&config.target_executable, &config.env, config.search_path)
+        .at(EntryStage::CheckInit)?;
+    check_init(&executable).at(EntryStage::CheckInit)?;
+
+    enter(report_pipe, EntryStage::Exec)?;
+    run_init(
+        &config.target_executable,
+        &config.args,
+        &config.env,
+        &executable,
+    )
+    .at(EntryStage::Exec)
+}
+
+// Moves one of our fds out of the way if redirect_standard_io() would
+// overwrite it.
+fn keep_clear_of(fd: RawFd, stdio_fds: &[(RawFd, RawFd)]) -> CommonResult<RawFd> {
+    if !stdio_fds.iter().any(|x| x.1 == fd) {
+        return Ok(fd);
+    }
+    let lowest = stdio_fds.iter().map(|x| x.1 + 1).max().unwrap_or(0);
+    let moved = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(lowest))?;
+    unistd::close(fd)?;
+    Ok(moved)
 }
 
-fn extract_pipes(rd_set: (RawFd, RawFd), rp_set: (RawFd, RawFd)) -> CommonResult<(RawFd, RawFd)> {
+pub(super) fn extract_pipes(
+    rd_set: (RawFd, RawFd),
+    rp_set: (RawFd, RawFd),
+    stdio_fds: &[(RawFd, RawFd)],
+) -> CommonResult<(RawFd, RawFd)> {
     let (rd_read, rd_write) = rd_set;
     let (rp_read, rp_write) = rp_set;
     unistd::close(rd_write)?;
     unistd::close(rp_read)?;
-    Ok((rd_read, rp_write))
+    Ok((
+        keep_clear_of(rd_read, stdio_fds)?,
+        keep_clear_of(rp_write, stdio_fds)?,
+    ))
 }
 
-#[allow(unused_must_use)]
 pub fn main(cfg: InternalData) -> isize {
-    let (ready_pipe, report_pipe) = extract_pipes(cfg.ready_pipe_set, cfg.report_pipe_set).unwrap();
-    match exceptable_main(cfg.config, ready_pipe, report_pipe) {
-        Err(err) => {
-            println!("Entry Error:\n{}\nEnd.\n", err);
-            unistd::write(report_pipe, &[1]);
-            let report_msg = format!("{}", err).to_string();
-            let report_msg_buf = report_msg.as_bytes();
-            unistd::write(report_pipe, &report_msg_buf.len().to_ne_bytes());
-            unistd::write(report_pipe, report_msg_buf);
+    let (ready_pipe, report_pipe) =
+        extract_pipes(cfg.ready_pipe_set, cfg.report_pipe_set, &cfg.stdio_fds).unwrap();
+    match exceptable_main(
+        cfg.config,
+        ready_pipe,
+        report_pipe,
+        cfg.stdio_fds,
+        cfg.controlling_tty,
+        cfg.setgroups_allowed,
+    ) {
+        Err(failure) => {
+            let _ = report::write_failure(report_pipe, &failure);
             -1
         }
         _ => unreachable!(),
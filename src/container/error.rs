@@ -1,30 +1,75 @@
-use std::fmt;
+use {nix::errno::Errno, std::fmt};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Error {
     ForkFailed(nix::Error),
     AlreadyStarted,
-    EntryError(EntryError),
+    NotStarted,
+    ExecutableNotFound(String),
+    UnmappedInnerId,
+    InvalidStdio,        // Bytes is only for stdin, Capture only for stdout and stderr
+    InvalidExtraFd(i32), // below 3 or used twice
+    MalformedReport,     // the child has sent something we do not understand
+    ExecFailed(Errno),   // execve(2) of the target, e.g. ENOEXEC
+    // the child has been killed in the stage it has announced last, if any
+    SetupTimedOut(Option<EntryStage>),
+    // the child has exited before exec without a report, e.g. it was killed
+    // by the OOM killer, in the stage it has announced last
+    ChildDied(Option<EntryStage>),
 }
 
-#[derive(Debug)]
-pub struct EntryError {
-    error_code: u8,
-    additional_info: String,
+// The part of the setup inside the container which has failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryStage {
+    Hostname,  // hostname and time namespace
+    Stdio,     // the given fds and the controlling terminal
+    Mount,     // the root filesystem, or joining the namespaces for an exec
+    Policy,    // cgroup namespace, network, rlimits, user and security policies
+    CheckInit, // looking up the executable
+    Exec,      // execve(2) itself
 }
 
-impl EntryError {
-    pub fn new(code: u8, buf: &[u8]) -> Self {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryFailure {
+    stage: EntryStage,
+    errno: Option<Errno>, // None if the error did not come from a syscall
+    message: String,
+}
+
+impl EntryFailure {
+    pub fn new(stage: EntryStage, errno: Option<Errno>, message: String) -> Self {
         Self {
-            error_code: code,
-            additional_info: String::from_utf8_lossy(buf).into_owned(),
+            stage,
+            errno,
+            message,
         }
     }
-}
 
-impl std::convert::Into<Error> for EntryError {
-    fn into(self) -> Error {
-        Error::EntryError(self)
+    // The errno is taken from the first syscall error in the chain of sources.
+    pub fn from_error(stage: EntryStage, err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut errno = None;
+        let mut cause = Some(err);
+        while let (None, Some(e)) = (errno, cause) {
+            if let Some(nix::Error::Sys(x)) = e.downcast_ref::<nix::Error>() {
+                errno = Some(*x);
+            } else if let Some(x) = e.downcast_ref::<std::io::Error>() {
+                errno = x.raw_os_error().map(Errno::from_i32);
+            }
+            cause = e.source();
+        }
+        Self::new(stage, errno, err.to_string())
+    }
+
+    pub fn stage(&self) -> EntryStage {
+        self.stage
+    }
+
+    pub fn errno(&self) -> Option<Errno> {
+        self.errno
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
     }
 }
 
@@ -0,0 +1,414 @@
+use {
+    super::{
+        check_inner_ids,
+        entry::{self, AtStage},
+        error::{self, EntryFailure, EntryStage},
+        pidfd::PidFd,
+        report,
+        result::TimeLimitKind,
+        stdio, wait_for_exit, watchdog, Config, Container, Pty, RunResult, Stdio,
+    },
+    crate::{network::NetworkMode, security::ApplySecurityPolicy, CommonResult, VoidResult},
+    nix::{
+        errno::Errno,
+        fcntl::{self, OFlag},
+        libc,
+        sys::{signal, stat::Mode, wait},
+        unistd::{self, ForkResult, Pid},
+    },
+    std::{
+        os::unix::io::RawFd,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+#[derive(Debug, Clone)]
+pub struct ExecSpec {
+    pub target_executable: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub search_path: bool,
+    pub inner_uid: Option<u32>, // None for Config::inner_uid
+    pub inner_gid: Option<u32>, // None for Config::inner_gid
+    pub time_limit: Option<Duration>,
+    pub stdin: Stdio,
+    pub stdout: Stdio,
+    pub stderr: Stdio,
+    pub extra_fds: Vec<(RawFd, RawFd)>, // (fd of ours, fd in the container)
+}
+
+impl Default for ExecSpec {
+    fn default() -> Self {
+        Self {
+            target_executable: "/bin/sh".into(),
+            args: Vec::new(),
+            env: Vec::new(),
+            search_path: false,
+            inner_uid: None,
+            inner_gid: None,
+            time_limit: None,
+            stdin: Default::default(),
+            stdout: Default::default(),
+            stderr: Default::default(),
+            extra_fds: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct InternalData {
+    config: Arc<Config>,
+    spec: Arc<ExecSpec>,
+    target_pid: Pid,
+    inner_uid: u32,
+    inner_gid: u32,
+    ready_pipe_set: (RawFd, RawFd),
+    report_pipe_set: (RawFd, RawFd),
+    stdio_fds: Vec<(RawFd, RawFd)>,
+    controlling_tty: Option<RawFd>,
+    setgroups_allowed: bool,
+}
+
+#[derive(Debug)]
+pub struct ExecHandle {
+    pid: Pid,
+    pidfd: Arc<PidFd>,
+    started_at: Instant,
+    result: Option<RunResult>,
+    time_limit_exceeded: Arc<Mutex<Option<TimeLimitKind>>>,
+    output_limit_exceeded: Arc<AtomicBool>,
+    stdio: Option<stdio::StdioHandle>,
+    pty: Option<Pty>,
+    abandoned: bool, // see abort_setup()
+}
+
+impl ExecHandle {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn pty(&self) -> Option<&Pty> {
+        self.pty.as_ref()
+    }
+
+    pub fn wait(&mut self) -> crate::Result<RunResult> {
+        if let Some(result) = &self.result {
+            return Ok(result.clone());
+        }
+
+        let (status, usage) = wait_for_exit(self.pid)?;
+        let wall_time = self.started_at.elapsed();
+        let (stdout, stderr) = match self.stdio.take() {
+            Some(x) => x.join(),
+            None => (None, None),
+        };
+
+        let mut result = RunResult::new(status, &usage, wall_time);
+        result.time_limit_exceeded = *self.time_limit_exceeded.lock().unwrap();
+        result.output_limit_exceeded = self.output_limit_exceeded.load(Ordering::SeqCst);
+        result.stdout = stdout;
+        result.stderr = stderr;
+
+        self.result = Some(result.clone());
+        Ok(result)
+    }
+
+    pub fn result(&self) -> Option<&RunResult> {
+        self.result.as_ref()
+    }
+
+    pub fn terminate(&mut self) -> crate::Result<()> {
+        if self.result.is_none() && !self.abandoned {
+            self.pidfd.kill()?;
+            self.wait()?;
+        }
+        Ok(())
+    }
+
+    // The process inside dies with the intermediate one by PR_SET_PDEATHSIG.
+    // Like Container::abort_setup(), it is not waited for longer than the
+    // setup, and left unreaped otherwise.
+    fn abort_setup(&mut self, timeout: Duration) -> crate::Result<()> {
+        self.pidfd.kill()?;
+        if self.pidfd.wait_timeout(timeout)? {
+            self.wait()?;
+        } else {
+            // the pumps end with the process, nobody joins them
+            self.stdio = None;
+            self.abandoned = true;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ExecHandle {
+    #[allow(unused_must_use)]
+    fn drop(&mut self) {
+        self.terminate();
+    }
+}
+
+impl Container {
+    pub fn exec(&mut self, spec: ExecSpec) -> crate::Result<ExecHandle> {
+        const STACK_SIZE: usize = 2 * 1024 * 1024; // 2048kb
+
+        let target_pid = match self.container_pid {
+            Some(pid) if !self.has_ened() => pid,
+            _ => return Err(error::Error::NotStarted.into()),
+        };
+
+        let inner_uid = spec.inner_uid.unwrap_or(self.config.inner_uid);
+        let inner_gid = spec.inner_gid.unwrap_or(self.config.inner_gid);
+        check_inner_ids(&self.id_mapping(), inner_uid, inner_gid)?;
+
+        let mut stack_memory = vec![0_u8; STACK_SIZE];
+
+        let (ready_pipe_read, ready_pipe_write) = unistd::pipe()?;
+        let (report_pipe_read, report_pipe_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let mut stdio = stdio::StdioSetup::new(
+            &spec.stdin,
+            &spec.stdout,
+            &spec.stderr,
+            &spec.extra_fds,
+            self.config.output_limit,
+        )?;
+
+        let time_limit = spec.time_limit;
+        let ic = InternalData {
+            config: self.config.clone(),
+            spec: Arc::new(spec),
+            target_pid,
+            inner_uid,
+            inner_gid,
+            ready_pipe_set: (ready_pipe_read, ready_pipe_write),
+            report_pipe_set: (report_pipe_read, report_pipe_write),
+            stdio_fds: stdio.child_fds(),
+            controlling_tty: stdio.controlling_tty(),
+            setgroups_allowed: self.setgroups_allowed,
+        };
+
+        let pid = match nix::sched::clone(
+            box || main(ic.clone()),
+            stack_memory.as_mut(),
+            nix::sched::CloneFlags::empty(),
+            Some(signal::SIGCHLD as i32),
+        ) {
+            Ok(x) => x,
+            Err(e) => return Err(error::Error::ForkFailed(e).into()),
+        };
+
+        unistd::close(ready_pipe_read)?;
+        unistd::close(report_pipe_write)?;
+
+        let pidfd = match PidFd::open(pid) {
+            Ok(x) => Arc::new(x),
+            Err(e) => {
+                signal::kill(pid, signal::SIGKILL)?;
+                wait_for_exit(pid)?;
+                return Err(e.into());
+            }
+        };
+
+        let output_limit_exceeded = Arc::new(AtomicBool::new(false));
+        let mut handle = ExecHandle {
+            pid,
+            pty: stdio.take_pty(),
+            stdio: Some(stdio.start(&pidfd, &output_limit_exceeded)),
+            pidfd,
+            started_at: Instant::now(),
+            result: None,
+            time_limit_exceeded: Arc::new(Mutex::new(None)),
+            output_limit_exceeded,
+            abandoned: false,
+        };
+
+        // joining the cgroup here lets every process forked inside inherit it
+        self.config.cgroup_limits.add_task(self.config.uid, pid)?;
+        unistd::close(ready_pipe_write)?;
+
+        let report = report::read_report(report_pipe_read, self.config.setup_timeout);
+        unistd::close(report_pipe_read)?;
+        let report = match report {
+            Err(crate::Error::Container(e @ error::Error::SetupTimedOut(_))) => {
+                handle.abort_setup(self.config.setup_timeout)?;
+                return Err(e.into());
+            }
+            x => x?,
+        };
+        if let Some(failure) = report {
+            handle.wait()?;
+            return Err(failure.into());
+        }
+
+        // the cpu time limit covers the whole cgroup and is watched by start()
+        if let Some(time_limit) = time_limit {
+            watchdog::spawn(watchdog::Watchdog::new(
+                self.config.uid,
+                Default::default(),
+                handle.pidfd.clone(),
+                time_limit,
+                None,
+                handle.time_limit_exceeded.clone(),
+            ));
+        }
+        handle.started_at = Instant::now();
+        Ok(handle)
+    }
+}
+
+fn open_namespaces(target_pid: Pid, config: &Config) -> CommonResult<Vec<(RawFd, libc::c_int)>> {
+    // the user namespace goes first, it grants the capabilities for the rest
+    let mut namespaces = vec![
+        ("user", libc::CLONE_NEWUSER),
+        ("ipc", libc::CLONE_NEWIPC),
+        ("uts", libc::CLONE_NEWUTS),
+        ("cgroup", libc::CLONE_NEWCGROUP),
+        ("pid", libc::CLONE_NEWPID),
+    ];
+    if config.network != NetworkMode::Host {
+        namespaces.push(("net", libc::CLONE_NEWNET));
+    }
+    if config.time_namespace {
+        namespaces.push(("time", libc::CLONE_NEWTIME));
+    }
+    namespaces.push(("mnt", libc::CLONE_NEWNS));
+
+    let mut fds = Vec::new();
+    for (name, nstype) in namespaces.into_iter() {
+        let fd = fcntl::open(
+            format!("/proc/{}/ns/{}", target_pid, name).as_str(),
+            OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        fds.push((fd, nstype));
+    }
+    Ok(fds)
+}
+
+fn join_namespaces(target_pid: Pid, config: &Config) -> VoidResult {
+    let namespaces = open_namespaces(target_pid, config)?;
+    // opened before joining the mount namespace, /proc is the host one here
+    let root = fcntl::open(
+        format!("/proc/{}/root", target_pid).as_str(),
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+
+    for (fd, nstype) in namespaces.into_iter() {
+        Errno::result(unsafe { libc::setns(fd, nstype) })?;
+        unistd::close(fd)?;
+    }
+
+    // setns(2) on a mount namespace does not follow a chroot(2) inside it
+    unistd::fchdir(root)?;
+    unistd::chroot(".")?;
+    unistd::chdir("/")?;
+    unistd::close(root)?;
+    Ok(())
+}
+
+// The intermediate process outside of the pid namespace may be killed, we
+// must not outlive it. It is not in our pid namespace, so getppid(2) gives 0
+// until we are reparented. The signal is only sent for a death after prctl(2).
+fn die_with_intermediate() -> nix::Result<()> {
+    Errno::result(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) })?;
+    if unistd::getppid() != Pid::from_raw(0) {
+        unsafe { libc::_exit(-1) }
+    }
+    Ok(())
+}
+
+fn exec_init(data: &InternalData, report_pipe: RawFd) -> Result<!, EntryFailure> {
+    let spec = &data.spec;
+    die_with_intermediate().at(EntryStage::Policy)?;
+    if let Some(fd) = data.controlling_tty {
+        entry::set_controlling_tty(fd).at(EntryStage::Stdio)?;
+    }
+
+    // we were forked from the host side, not from the container
+    data.config.rlimits.apply().at(EntryStage::Policy)?;
+    entry::switch_user(data.inner_uid, data.inner_gid, data.setgroups_allowed)
+        .at(EntryStage::Policy)?;
+    // changing the credentials has cleared the parent death signal
+    die_with_intermediate().at(EntryStage::Policy)?;
+    entry::apply_security_policy(&data.config.security_policies).at(EntryStage::Policy)?;
+    entry::enter(report_pipe, EntryStage::CheckInit)?;
+    let executable =
+        entry::resolve_executable(&spec.target_executable, &spec.env, spec.search_path)
+            .at(EntryStage::CheckInit)?;
+    entry::check_init(&executable).at(EntryStage::CheckInit)?;
+
+    entry::enter(report_pipe, EntryStage::Exec)?;
+    entry::run_init(&spec.target_executable, &spec.args, &spec.env, &executable)
+        .at(EntryStage::Exec)
+}
+
+fn forward_exit(child: Pid) -> isize {
+    loop {
+        match wait::waitpid(child, None) {
+            Ok(wait::WaitStatus::Exited(_, code)) => return code as isize,
+            Ok(wait::WaitStatus::Signaled(_, sig, _)) => {
+                unsafe {
+                    let _ = signal::signal(sig, signal::SigHandler::SigDfl);
+                }
+                let _ = signal::raise(sig);
+                return 128 + sig as isize;
+            }
+            Err(nix::Error::Sys(Errno::EINTR)) | Ok(_) => continue,
+            Err(_) => return -1,
+        }
+    }
+}
+
+fn exceptable_main(
+    data: &InternalData,
+    ready_pipe: RawFd,
+    report_pipe: RawFd,
+) -> Result<Pid, EntryFailure> {
+    entry::enter(report_pipe, EntryStage::Stdio)?;
+    entry::redirect_standard_io(&data.stdio_fds).at(EntryStage::Stdio)?;
+    // like the container, while /proc is still the one of the host
+    let mut keep_fds: Vec<RawFd> = data.stdio_fds.iter().map(|x| x.1).collect();
+    keep_fds.extend_from_slice(&[ready_pipe, report_pipe]);
+    entry::close_inherited_fds(&keep_fds).at(EntryStage::Stdio)?;
+    entry::enter(report_pipe, EntryStage::Mount)?;
+    join_namespaces(data.target_pid, &data.config).at(EntryStage::Mount)?;
+
+    // the parent has moved us into the cgroup of the container
+    entry::enter(report_pipe, EntryStage::Policy)?;
+    entry::block_until_ready(ready_pipe).at(EntryStage::Policy)?;
+
+    // joining a pid namespace only applies to the children,
+    // we are single threaded here so fork(2) is fine
+    match unsafe { unistd::fork() }.at(EntryStage::Policy)? {
+        ForkResult::Child => match exec_init(data, report_pipe) {
+            Err(failure) => {
+                let _ = report::write_failure(report_pipe, &failure);
+                unsafe { libc::_exit(-1) }
+            }
+            _ => unreachable!(),
+        },
+        ForkResult::Parent { child } => Ok(child),
+    }
+}
+
+fn main(data: InternalData) -> isize {
+    let (ready_pipe, report_pipe) =
+        entry::extract_pipes(data.ready_pipe_set, data.report_pipe_set, &data.stdio_fds).unwrap();
+    match exceptable_main(&data, ready_pipe, report_pipe) {
+        Ok(child) => {
+            // we never exec, whatever we hold would stay open as long as the
+            // process runs, e.g. the write end of a Stdio::Bytes pipe
+            let _ = entry::close_inherited_fds(&[]);
+            forward_exit(child)
+        }
+        Err(failure) => {
+            let _ = report::write_failure(report_pipe, &failure);
+            -1
+        }
+    }
+}
@@ -1,20 +1,47 @@
 use {
     crate::{
-        filesystem::MountNamespacedFs,
-        idmap,
+        filesystem::{MountNamespacedFs, RootMode},
+        idmap::IdMapping,
+        network::NetworkMode,
         resource::CGroupLimitPolicy,
-        security::{self, ApplySecurityPolicy},
-        VoidResult,
+        security::{self, ApplySecurityPolicy, RlimitPolicy},
+        Result, Within,
     },
     nix::{
-        sys::signal,
+        fcntl::OFlag,
+        libc,
+        sys::{signal, wait::WaitStatus},
         unistd::{self, Pid},
     },
-    std::sync::Arc,
+    std::{
+        os::unix::io::RawFd,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
 };
 
+#[cfg(feature = "async")]
+mod asynchronous;
 mod entry;
 mod error;
+mod exec;
+mod pidfd;
+mod pty;
+mod report;
+mod result;
+mod stdio;
+mod watchdog;
+
+#[cfg(feature = "async")]
+pub use asynchronous::AsyncContainer;
+pub use error::{EntryFailure, EntryStage, Error};
+pub use exec::{ExecHandle, ExecSpec};
+pub use pty::Pty;
+pub use result::{ExitStatus, RunResult, TimeLimitKind};
+pub use stdio::Stdio;
 
 #[derive(Debug)]
 pub struct Config {
@@ -22,15 +49,30 @@ pub struct Config {
     pub working_path: String,
     pub hostname: String,
     pub target_executable: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub search_path: bool, // look up target_executable in PATH inside the new root
+    pub network: NetworkMode,
+    pub time_namespace: bool, // start monotonic and boottime clocks near zero, needs linux 5.6
     pub fs: Vec<Box<dyn MountNamespacedFs>>,
+    pub root_mode: RootMode,
     pub security_policies: Vec<Box<dyn ApplySecurityPolicy>>,
+    pub rlimits: RlimitPolicy,
     pub cgroup_limits: Box<CGroupLimitPolicy>,
-    pub inner_uid: u32, // uid inside container
-    pub inner_gid: u32, // gid inside container
+    // 0 keeps the capabilities that CapabilityPolicy allows, any other none
+    pub inner_uid: u32,                // uid inside container
+    pub inner_gid: u32,                // gid inside container
+    pub id_mapping: Option<IdMapping>, // None maps only inner_uid and inner_gid
+    // wall clock, the cpu time limit is set in cgroup_limits
     pub time_limit: std::time::Duration,
-    pub stdin: Option<String>,
-    pub stdout: Option<String>,
-    pub stderr: Option<String>,
+    // for start() and exec() to hear back from the child, which may hang on
+    // e.g. a stuck bind mount source
+    pub setup_timeout: std::time::Duration,
+    pub stdin: Stdio,
+    pub stdout: Stdio,
+    pub stderr: Stdio,
+    pub extra_fds: Vec<(RawFd, RawFd)>, // (fd of ours, fd in the container)
+    pub output_limit: Option<u64>,      // in bytes, for stdout and stderr each
 }
 
 impl Default for Config {
@@ -40,18 +82,29 @@ impl Default for Config {
             working_path: "/tmp/ssandbox-rs.workspace/".to_string(),
             hostname: "container".to_string(),
             target_executable: "/bin/sh".into(),
+            args: Vec::new(),
+            env: Vec::new(),
+            search_path: false,
+            network: Default::default(),
+            time_namespace: false,
             fs: Vec::new(),
+            root_mode: Default::default(),
             security_policies: vec![
                 box (Default::default(): security::CapabilityPolicy),
                 box (Default::default(): security::SeccompPolicy),
             ],
+            rlimits: Default::default(),
             cgroup_limits: Default::default(),
             inner_gid: 0,
             inner_uid: 0,
+            id_mapping: None,
             time_limit: std::time::Duration::from_secs(1),
-            stdin: None,
-            stdout: None,
-            stderr: None,
+            setup_timeout: std::time::Duration::from_secs(10),
+            stdin: Default::default(),
+            stdout: Default::default(),
+            stderr: Default::default(),
+            extra_fds: Vec::new(),
+            output_limit: None,
         }
     }
 }
@@ -60,7 +113,17 @@ impl Default for Config {
 pub struct Container {
     config: Arc<Config>,
     container_pid: Option<Pid>,
+    pidfd: Option<Arc<pidfd::PidFd>>,
     already_ended: bool,
+    started_at: Option<Instant>,
+    result: Option<RunResult>,
+    entry_failure: Option<EntryFailure>,
+    setup_error: Option<Error>, // the child has not reported how the setup ended
+    time_limit_exceeded: Arc<Mutex<Option<TimeLimitKind>>>,
+    output_limit_exceeded: Arc<AtomicBool>,
+    stdio: Arc<Mutex<Option<stdio::StdioHandle>>>,
+    pty: Option<Arc<Pty>>,
+    setgroups_allowed: bool,
 }
 
 impl std::convert::From<Config> for Container {
@@ -68,7 +131,17 @@ impl std::convert::From<Config> for Container {
         Self {
             config: Arc::new(source),
             container_pid: None,
+            pidfd: None,
             already_ended: false,
+            started_at: None,
+            result: None,
+            entry_failure: None,
+            setup_error: None,
+            time_limit_exceeded: Arc::new(Mutex::new(None)),
+            output_limit_exceeded: Arc::new(AtomicBool::new(false)),
+            stdio: Arc::new(Mutex::new(None)),
+            pty: None,
+            setgroups_allowed: true,
         }
     }
 }
@@ -78,7 +151,17 @@ impl std::convert::From<Arc<Config>> for Container {
         Self {
             config: source,
             container_pid: None,
+            pidfd: None,
             already_ended: false,
+            started_at: None,
+            result: None,
+            entry_failure: None,
+            setup_error: None,
+            time_limit_exceeded: Arc::new(Mutex::new(None)),
+            output_limit_exceeded: Arc::new(AtomicBool::new(false)),
+            stdio: Arc::new(Mutex::new(None)),
+            pty: None,
+            setgroups_allowed: true,
         }
     }
 }
@@ -88,7 +171,17 @@ impl Container {
         Self {
             config: Arc::new(Default::default()),
             container_pid: None,
+            pidfd: None,
             already_ended: false,
+            started_at: None,
+            result: None,
+            entry_failure: None,
+            setup_error: None,
+            time_limit_exceeded: Arc::new(Mutex::new(None)),
+            output_limit_exceeded: Arc::new(AtomicBool::new(false)),
+            stdio: Arc::new(Mutex::new(None)),
+            pty: None,
+            setgroups_allowed: true,
         }
     }
 
@@ -100,23 +193,61 @@ impl Container {
         self.already_ended
     }
 
-    pub fn start(&mut self) -> VoidResult {
+    pub fn start(&mut self) -> Result<()> {
+        let report_pipe_read = self.launch()?;
+
+        // our child maybe now complaining about errors
+        let report = report::read_report(report_pipe_read, self.config.setup_timeout);
+        unistd::close(report_pipe_read)?;
+        if let Err(e) = self.check_report(report) {
+            if self.timed_out() {
+                self.abort_setup()?;
+            }
+            return Err(e);
+        }
+
+        // the wall time, like the time limit, starts once the target runs
+        self.started_at = Some(Instant::now());
+        watchdog::spawn(self.watchdog());
+
+        Ok(())
+    }
+
+    // Clones the container and releases it once everything is set up from
+    // outside, returns the pipe on which the child reports its start.
+    fn launch(&mut self) -> Result<RawFd> {
         const STACK_SIZE: usize = 2 * 1024 * 1024; // 2048kb
 
         if self.has_started() || self.has_ened() {
-            return Err(box error::Error::AlreadyStarted);
+            return Err(error::Error::AlreadyStarted.into());
         }
 
+        let id_mapping = self.id_mapping();
+        check_inner_ids(&id_mapping, self.config.inner_uid, self.config.inner_gid)?;
+        self.config.rlimits.validate()?;
+
         let mut stack_memory = Vec::new();
         stack_memory.resize(STACK_SIZE, 0);
 
         let (ready_pipe_read, ready_pipe_write) = nix::unistd::pipe()?;
-        let (report_pipe_read, report_pipe_write) = nix::unistd::pipe()?;
+        // closed on exec, see report::read_report()
+        let (report_pipe_read, report_pipe_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let mut stdio = stdio::StdioSetup::new(
+            &self.config.stdin,
+            &self.config.stdout,
+            &self.config.stderr,
+            &self.config.extra_fds,
+            self.config.output_limit,
+        )?;
 
+        self.setgroups_allowed = id_mapping.setgroups_allowed();
         let ic = entry::InternalData {
             config: self.config.clone(),
             ready_pipe_set: (ready_pipe_read, ready_pipe_write),
             report_pipe_set: (report_pipe_read, report_pipe_write),
+            stdio_fds: stdio.child_fds(),
+            controlling_tty: stdio.controlling_tty(),
+            setgroups_allowed: self.setgroups_allowed,
         };
 
         use nix::sched::CloneFlags;
@@ -127,24 +258,42 @@ impl Container {
                 | CloneFlags::CLONE_NEWIPC
                 | CloneFlags::CLONE_NEWPID
                 | CloneFlags::CLONE_NEWNS
-                | CloneFlags::CLONE_NEWUSER,
+                | CloneFlags::CLONE_NEWUSER
+                | self.config.network.clone_flags(),
             Some(signal::SIGCHLD as i32),
         ) {
             Ok(x) => x,
-            Err(e) => return Err(box error::Error::ForkFailed(e)),
+            Err(e) => return Err(error::Error::ForkFailed(e).into()),
         };
         self.container_pid = Some(pid);
 
+        // nobody can have reaped the child yet, so the pid still refers to it
+        match pidfd::PidFd::open(pid) {
+            Ok(x) => self.pidfd = Some(Arc::new(x)),
+            Err(e) => {
+                signal::kill(pid, signal::SIGKILL)?;
+                return Err(e.into());
+            }
+        }
+
         unistd::close(ready_pipe_read)?;
         unistd::close(report_pipe_write)?;
 
-        match (|| -> VoidResult {
-            idmap::map_to_root(pid)?;
+        self.pty = stdio.take_pty().map(Arc::new);
+        *self.stdio.lock().unwrap() =
+            Some(stdio.start(self.pidfd.as_ref().unwrap(), &self.output_limit_exceeded));
+
+        match (|| -> Result<()> {
+            id_mapping.apply(pid).within(crate::Error::IdMap)?;
             self.config.cgroup_limits.apply(self.config.uid, pid)?;
+            let network = &self.config.network;
+            network
+                .setup_outside(self.config.uid, pid)
+                .within(crate::Error::Network)?;
             Ok(())
         })() {
             Err(x) => {
-                signal::kill(pid, signal::SIGKILL)?;
+                self.pidfd.as_ref().unwrap().kill()?;
                 return Err(x);
             }
             _ => {}
@@ -155,79 +304,198 @@ impl Container {
         // So that the real command can be executed via execvp().
         unistd::close(ready_pipe_write)?;
 
-        // our child maybe now complaining about errors
-        let mut child_status_buf = [0_u8; 1];
-        unistd::read(report_pipe_read, &mut child_status_buf)?;
-        if child_status_buf[0] != 0 {
-            let code = child_status_buf[0];
-            let mut addtional_info_length_buf = [0_u8; std::mem::size_of::<usize>()];
-            unistd::read(report_pipe_read, &mut addtional_info_length_buf)?;
-            let addtional_info_length = usize::from_ne_bytes(addtional_info_length_buf);
-
-            let mut addtional_info_buf = Vec::new();
-            addtional_info_buf.resize(addtional_info_length, 0);
-            unistd::read(report_pipe_read, &mut addtional_info_buf)?;
-
-            let wrapped_error: error::Error =
-                error::EntryError::new(code, &addtional_info_buf).into();
-            return Err(box wrapped_error);
-        }
-
-        let time_limit = self.config.time_limit.clone();
-        std::thread::spawn(move || {
-            std::thread::sleep(time_limit);
-
-            use nix::sys::wait;
-            match wait::waitpid(pid, Some(wait::WaitPidFlag::WNOHANG)) {
-                Ok(wait::WaitStatus::StillAlive) => {
-                    let _ = signal::kill(pid, signal::SIGKILL);
-                }
-                _ => {}
-            };
-        });
+        Ok(report_pipe_read)
+    }
 
-        Ok(())
+    fn id_mapping(&self) -> IdMapping {
+        match &self.config.id_mapping {
+            Some(x) => x.clone(),
+            None => IdMapping::single(self.config.inner_uid, self.config.inner_gid),
+        }
     }
 
-    pub fn wait(&mut self) -> VoidResult {
-        if !self.has_ened() {
-            if let Some(pid) = self.container_pid {
-                nix::sys::wait::waitpid(pid, None)?;
+    fn watchdog(&self) -> watchdog::Watchdog {
+        watchdog::Watchdog::new(
+            self.config.uid,
+            (*self.config.cgroup_limits).clone(),
+            self.pidfd.clone().unwrap(),
+            self.config.time_limit,
+            self.config.cgroup_limits.time_limit(),
+            self.time_limit_exceeded.clone(),
+        )
+    }
+
+    // Keeps how the setup has failed for verdict::judge().
+    fn check_report(&mut self, report: Result<Option<EntryFailure>>) -> Result<()> {
+        match report {
+            Ok(Some(failure)) => {
+                self.entry_failure = Some(failure.clone());
+                Err(failure.into())
+            }
+            Err(crate::Error::Container(
+                e @ (error::Error::SetupTimedOut(_) | error::Error::ChildDied(_)),
+            )) => {
+                self.setup_error = Some(e.clone());
+                Err(e.into())
             }
-            self.already_ended = true;
+            x => x.map(|_| ()),
+        }
+    }
+
+    fn timed_out(&self) -> bool {
+        matches!(self.setup_error, Some(error::Error::SetupTimedOut(_)))
+    }
+
+    // A process in an uninterruptible sleep only dies once the sleep ends, so
+    // it is not waited for longer than the setup. delete() removes the cgroup
+    // if it is still there.
+    fn abort_setup(&mut self) -> Result<()> {
+        let pidfd = self.pidfd.clone().unwrap();
+        pidfd.kill()?;
+        if pidfd.wait_timeout(self.config.setup_timeout)? {
+            self.wait()?;
+            self.config.cgroup_limits.delete(self.config.uid)?;
         }
         Ok(())
     }
 
-    pub fn terminate(&mut self) -> VoidResult {
+    pub fn wait(&mut self) -> Result<RunResult> {
+        if let Some(result) = &self.result {
+            return Ok(result.clone());
+        }
+
+        let pid = match self.container_pid {
+            Some(pid) => pid,
+            None => return Err(error::Error::NotStarted.into()),
+        };
+
+        let (status, usage) = wait_for_exit(pid)?;
+        let wall_time = self.wall_time();
+        self.already_ended = true;
+        let output = join_stdio(&self.stdio);
+        Ok(self.finish(status, &usage, wall_time, output))
+    }
+
+    fn wall_time(&self) -> Duration {
+        self.started_at.map(|x| x.elapsed()).unwrap_or_default()
+    }
+
+    // Puts the result together once the process is reaped.
+    fn finish(
+        &mut self,
+        status: ExitStatus,
+        usage: &libc::rusage,
+        wall_time: Duration,
+        (stdout, stderr): (Option<Vec<u8>>, Option<Vec<u8>>),
+    ) -> RunResult {
+        let mut result = RunResult::new(status, usage, wall_time);
+        result.time_limit_exceeded = *self.time_limit_exceeded.lock().unwrap();
+        result.output_limit_exceeded = self.output_limit_exceeded.load(Ordering::SeqCst);
+        result.stdout = stdout;
+        result.stderr = stderr;
+
+        // the cgroup is removed in delete(), so these must be read now
+        let limits = &self.config.cgroup_limits;
+        result.peak_memory = limits.peak_memory(self.config.uid).unwrap_or(None);
+        result.oom_kills = limits.oom_kill_count(self.config.uid).unwrap_or(0);
+        result.refused_forks = limits.refused_fork_count(self.config.uid).unwrap_or(0);
+
+        self.result = Some(result.clone());
+        result
+    }
+
+    pub fn result(&self) -> Option<&RunResult> {
+        self.result.as_ref()
+    }
+
+    pub fn entry_failure(&self) -> Option<&EntryFailure> {
+        self.entry_failure.as_ref()
+    }
+
+    // SetupTimedOut or ChildDied, if start() has failed with it.
+    pub fn setup_error(&self) -> Option<&Error> {
+        self.setup_error.as_ref()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Only if one of the standard streams is Stdio::Pty.
+    pub fn pty(&self) -> Option<&Pty> {
+        self.pty.as_deref()
+    }
+
+    pub fn terminate(&mut self) -> Result<()> {
         if !self.has_ened() {
-            if let Some(pid) = self.container_pid {
-                signal::kill(pid, signal::SIGKILL)?;
+            if let Some(pidfd) = &self.pidfd {
+                pidfd.kill()?;
+                self.wait()?;
             }
-            self.wait()?;
         }
         Ok(())
     }
 
-    pub fn delete(&mut self) -> VoidResult {
+    pub fn delete(&mut self) -> Result<()> {
         let uid = self.config.uid;
         self.terminate()?;
-        self.config.cgroup_limits.delete(uid)?;
-        std::fs::remove_dir_all(
+        // each step is tried, a failing one does not leave the rest behind
+        let cgroup = self.config.cgroup_limits.delete(uid);
+        let network = self
+            .config
+            .network
+            .teardown(uid)
+            .within(crate::Error::Network);
+        let workspace = std::fs::remove_dir_all(
             std::path::PathBuf::from(&self.config.working_path).join(format!("{}", uid)),
-        )?;
-        Ok(())
+        );
+        cgroup.and(network).and(workspace.map_err(Into::into))
     }
 
-    pub fn freeze(&self) -> VoidResult {
+    pub fn freeze(&self) -> Result<()> {
         self.config.cgroup_limits.freeze(self.config.uid)
     }
 
-    pub fn thaw(&self) -> VoidResult {
+    pub fn thaw(&self) -> Result<()> {
         self.config.cgroup_limits.thaw(self.config.uid)
     }
 }
 
+// Waits until the pipes are closed, see StdioHandle::join().
+fn join_stdio(stdio: &Mutex<Option<stdio::StdioHandle>>) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+    match stdio.lock().unwrap().take() {
+        Some(x) => x.join(),
+        None => (None, None),
+    }
+}
+
+fn check_inner_ids(id_mapping: &IdMapping, inner_uid: u32, inner_gid: u32) -> Result<()> {
+    if !id_mapping.contains_uid(inner_uid) || !id_mapping.contains_gid(inner_gid) {
+        return Err(error::Error::UnmappedInnerId.into());
+    }
+    Ok(())
+}
+
+fn wait_for_exit(pid: Pid) -> Result<(ExitStatus, libc::rusage)> {
+    loop {
+        let mut status: libc::c_int = 0;
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        let res = unsafe { libc::wait4(pid.as_raw(), &mut status, 0, &mut usage) };
+        if res < 0 {
+            match nix::errno::Errno::last() {
+                nix::errno::Errno::EINTR => continue,
+                errno => return Err(nix::Error::Sys(errno).into()),
+            }
+        }
+
+        match WaitStatus::from_raw(pid, status)? {
+            WaitStatus::Exited(_, code) => return Ok((ExitStatus::Exited(code), usage)),
+            WaitStatus::Signaled(_, sig, _) => return Ok((ExitStatus::Signaled(sig), usage)),
+            _ => continue,
+        }
+    }
+}
+
 impl Drop for Container {
     #[allow(unused_must_use)]
     fn drop(&mut self) {
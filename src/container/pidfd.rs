@@ -0,0 +1,84 @@
+use {
+    nix::{
+        errno::Errno,
+        libc,
+        poll::{self, PollFd, PollFlags},
+        sys::signal::Signal,
+        unistd::{self, Pid},
+    },
+    std::{
+        os::unix::io::{AsRawFd, RawFd},
+        time::{Duration, Instant},
+    },
+};
+
+// A pidfd keeps referring to the same process even after its pid is reused.
+#[derive(Debug)]
+pub struct PidFd {
+    fd: RawFd,
+}
+
+impl PidFd {
+    // Only valid before the process is reaped, i.e. right after clone(2).
+    pub fn open(pid: Pid) -> nix::Result<Self> {
+        let fd = Errno::result(unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) })?;
+        Ok(Self { fd: fd as RawFd })
+    }
+
+    #[cfg(feature = "async")]
+    pub fn try_clone(&self) -> nix::Result<Self> {
+        use nix::fcntl::{self, FcntlArg};
+        let fd = fcntl::fcntl(self.fd, FcntlArg::F_DUPFD_CLOEXEC(0))?;
+        Ok(Self { fd })
+    }
+
+    pub fn send_signal(&self, sig: Signal) -> nix::Result<()> {
+        Errno::result(unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.fd,
+                sig as libc::c_int,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        })?;
+        Ok(())
+    }
+
+    pub fn kill(&self) -> nix::Result<()> {
+        match self.send_signal(Signal::SIGKILL) {
+            // the process has already exited
+            Err(nix::Error::Sys(Errno::ESRCH)) => Ok(()),
+            x => x,
+        }
+    }
+
+    // Returns whether the process has exited within the timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> nix::Result<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let millis = remaining.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            let mut fds = [PollFd::new(self.fd, PollFlags::POLLIN)];
+            match poll::poll(&mut fds, millis) {
+                Ok(0) if remaining.as_millis() <= millis as u128 => return Ok(false),
+                Ok(0) => continue,
+                Ok(_) => return Ok(true),
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl AsRawFd for PidFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for PidFd {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.fd);
+    }
+}
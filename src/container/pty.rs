@@ -0,0 +1,65 @@
+use {
+    crate::Result,
+    nix::{
+        errno::Errno,
+        fcntl::{self, FcntlArg, FdFlag},
+        libc, pty,
+    },
+    std::{
+        fs::File,
+        io,
+        os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    },
+};
+
+// The master side of the terminal of a container. What is written to it is
+// the input of the program, reading it returns EIO once the program is gone.
+#[derive(Debug)]
+pub struct Pty {
+    master: File,
+}
+
+impl Pty {
+    // Returns the master and the slave, the slave is opened with O_NOCTTY so
+    // it does not become our own controlling terminal.
+    pub(super) fn open() -> Result<(Self, File)> {
+        let pair = pty::openpty(None, None)?;
+        let master = unsafe { File::from_raw_fd(pair.master) };
+        let slave = unsafe { File::from_raw_fd(pair.slave) };
+        fcntl::fcntl(pair.master, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        fcntl::fcntl(pair.slave, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        Ok((Self { master }, slave))
+    }
+
+    // e.g. for a thread that copies the output while another writes the input
+    pub fn try_clone(&self) -> io::Result<File> {
+        self.master.try_clone()
+    }
+
+    // Returns (rows, columns).
+    pub fn window_size(&self) -> Result<(u16, u16)> {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        Errno::result(unsafe {
+            libc::ioctl(self.master.as_raw_fd(), libc::TIOCGWINSZ, &mut size)
+        })?;
+        Ok((size.ws_row, size.ws_col))
+    }
+
+    // The foreground process group of the terminal gets a SIGWINCH.
+    pub fn resize(&self, rows: u16, columns: u16) -> Result<()> {
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: columns,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        Errno::result(unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) })?;
+        Ok(())
+    }
+}
+
+impl AsRawFd for Pty {
+    fn as_raw_fd(&self) -> RawFd {
+        self.master.as_raw_fd()
+    }
+}
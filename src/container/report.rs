@@ -0,0 +1,192 @@
+use {
+    super::error::{self, EntryFailure, EntryStage},
+    crate::{Result, VoidResult},
+    nix::{
+        errno::Errno,
+        libc,
+        poll::{self, PollFd, PollFlags},
+        unistd,
+    },
+    std::{
+        os::unix::io::RawFd,
+        time::{Duration, Instant},
+    },
+};
+
+// The report pipe is closed on exec, so the end of file after the EXEC stage
+// means that the target has been executed. The child announces every stage
+// it enters, and if it fails it tells us why in a last frame. A frame is the
+// kind and the length of the payload, followed by the payload.
+//
+//   FAILURE: stage (u8), errno (i32, 0 for none), message (utf-8)
+//   STAGE:   stage (u8)
+//
+// Numbers are in native byte order, both ends are the same binary.
+pub(super) const HEADER_LEN: usize = 5;
+const FAILURE: u8 = 1;
+const STAGE: u8 = 2;
+
+// A whole frame fits into PIPE_BUF, so it is written at once.
+const MAX_PAYLOAD: usize = 4096 - HEADER_LEN;
+const MAX_MESSAGE: usize = MAX_PAYLOAD - 5;
+
+const STAGES: [EntryStage; 6] = [
+    EntryStage::Hostname,
+    EntryStage::Stdio,
+    EntryStage::Mount,
+    EntryStage::Policy,
+    EntryStage::CheckInit,
+    EntryStage::Exec,
+];
+
+pub(super) enum Frame {
+    Failure(EntryFailure),
+    Stage(EntryStage),
+}
+
+fn frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+    buf.push(kind);
+    buf.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+fn write_all(fd: RawFd, mut buf: &[u8]) -> VoidResult {
+    while !buf.is_empty() {
+        match unistd::write(fd, buf) {
+            Ok(n) => buf = &buf[n..],
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(box e),
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn write_failure(fd: RawFd, failure: &EntryFailure) -> VoidResult {
+    let mut message = failure.message();
+    if message.len() > MAX_MESSAGE {
+        let mut end = MAX_MESSAGE;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message = &message[..end];
+    }
+
+    let mut payload = Vec::with_capacity(5 + message.len());
+    payload.push(STAGES.iter().position(|x| *x == failure.stage()).unwrap() as u8);
+    payload.extend_from_slice(&failure.errno().map_or(0, |x| x as i32).to_ne_bytes());
+    payload.extend_from_slice(message.as_bytes());
+    write_all(fd, &frame(FAILURE, &payload))
+}
+
+pub(super) fn write_stage(fd: RawFd, stage: EntryStage) -> VoidResult {
+    let stage = STAGES.iter().position(|x| *x == stage).unwrap() as u8;
+    write_all(fd, &frame(STAGE, &[stage]))
+}
+
+fn parse_stage(stage: u8) -> Result<EntryStage> {
+    match STAGES.get(stage as usize) {
+        Some(x) => Ok(*x),
+        None => Err(error::Error::MalformedReport.into()),
+    }
+}
+
+// Returns the kind and the length of the payload which follows.
+pub(super) fn parse_header(header: &[u8]) -> Result<(u8, usize)> {
+    if header.len() != HEADER_LEN {
+        return Err(error::Error::MalformedReport.into());
+    }
+    let mut len = [0_u8; 4];
+    len.copy_from_slice(&header[1..]);
+    let len = u32::from_ne_bytes(len) as usize;
+    match header[0] {
+        FAILURE if (5..=MAX_PAYLOAD).contains(&len) => Ok((FAILURE, len)),
+        STAGE if len == 1 => Ok((STAGE, len)),
+        _ => Err(error::Error::MalformedReport.into()),
+    }
+}
+
+pub(super) fn parse_payload(kind: u8, payload: &[u8]) -> Result<Frame> {
+    let stage = parse_stage(payload[0])?;
+    if kind == STAGE {
+        return Ok(Frame::Stage(stage));
+    }
+    let mut errno = [0_u8; 4];
+    errno.copy_from_slice(&payload[1..5]);
+    let errno = match i32::from_ne_bytes(errno) {
+        0 => None,
+        x => Some(Errno::from_i32(x)),
+    };
+    let message = String::from_utf8_lossy(&payload[5..]).into_owned();
+    Ok(Frame::Failure(EntryFailure::new(stage, errno, message)))
+}
+
+// Returns whether fd has become readable before the deadline.
+fn poll_until(fd: RawFd, deadline: Instant) -> Result<bool> {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let millis = remaining.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll::poll(&mut fds, millis) {
+            Ok(0) if remaining.as_millis() <= millis as u128 => return Ok(false),
+            Ok(0) => continue,
+            Ok(_) => return Ok(true),
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// Tolerates short reads, returns less than buf.len() only at the end of file,
+// and None if the deadline has passed.
+fn read_full(fd: RawFd, buf: &mut [u8], deadline: Instant) -> Result<Option<usize>> {
+    let mut offset = 0;
+    while offset < buf.len() {
+        if !poll_until(fd, deadline)? {
+            return Ok(None);
+        }
+        match unistd::read(fd, &mut buf[offset..]) {
+            Ok(0) => break,
+            Ok(n) => offset += n,
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(Some(offset))
+}
+
+// Anywhere but after the EXEC stage the child has died without telling us.
+pub(super) fn end_of_report(last_stage: Option<EntryStage>) -> Result<Option<EntryFailure>> {
+    match last_stage {
+        Some(EntryStage::Exec) => Ok(None),
+        stage => Err(error::Error::ChildDied(stage).into()),
+    }
+}
+
+// Blocks until the child has either executed the target or failed, but not
+// longer than the timeout.
+pub(super) fn read_report(fd: RawFd, timeout: Duration) -> Result<Option<EntryFailure>> {
+    let deadline = Instant::now() + timeout;
+    let mut last_stage = None;
+    let timed_out = |stage| Err(error::Error::SetupTimedOut(stage).into());
+    loop {
+        let mut header = [0_u8; HEADER_LEN];
+        let n = match read_full(fd, &mut header, deadline)? {
+            Some(0) => return end_of_report(last_stage),
+            Some(n) => n,
+            None => return timed_out(last_stage),
+        };
+        let (kind, len) = parse_header(&header[..n])?;
+        let mut payload = vec![0_u8; len];
+        match read_full(fd, &mut payload, deadline)? {
+            Some(n) if n < len => return Err(error::Error::MalformedReport.into()),
+            Some(_) => {}
+            None => return timed_out(last_stage),
+        }
+        match parse_payload(kind, &payload)? {
+            Frame::Stage(stage) => last_stage = Some(stage),
+            Frame::Failure(failure) => return Ok(Some(failure)),
+        }
+    }
+}
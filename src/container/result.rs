@@ -0,0 +1,78 @@
+use {
+    nix::{libc, sys::signal::Signal},
+    std::time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(i32),
+    Signaled(Signal),
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        *self == ExitStatus::Exited(0)
+    }
+
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            ExitStatus::Exited(code) => Some(*code),
+            _ => None,
+        }
+    }
+
+    pub fn signal(&self) -> Option<Signal> {
+        match self {
+            ExitStatus::Signaled(sig) => Some(*sig),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeLimitKind {
+    Wall,   // Config::time_limit
+    Cpu,    // CGroupLimitPolicy::set_time_limit
+    Rlimit, // Resource::CpuTime, the soft limit is reported
+}
+
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    pub status: ExitStatus,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    pub wall_time: Duration,
+    pub peak_memory: Option<u64>, // in bytes, read from the memory cgroup
+    pub oom_kills: u64,
+    pub refused_forks: u64,
+    pub time_limit_exceeded: Option<TimeLimitKind>, // the limit the watchdog killed it for
+    pub output_limit_exceeded: bool,
+    pub stdout: Option<Vec<u8>>, // only for Stdio::Capture
+    pub stderr: Option<Vec<u8>>, // only for Stdio::Capture
+}
+
+impl RunResult {
+    pub fn new(status: ExitStatus, usage: &libc::rusage, wall_time: Duration) -> Self {
+        Self {
+            status,
+            user_time: timeval_to_duration(&usage.ru_utime),
+            system_time: timeval_to_duration(&usage.ru_stime),
+            wall_time,
+            peak_memory: None,
+            oom_kills: 0,
+            refused_forks: 0,
+            time_limit_exceeded: None,
+            output_limit_exceeded: false,
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+fn timeval_to_duration(tv: &libc::timeval) -> Duration {
+    Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+}
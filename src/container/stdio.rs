@@ -0,0 +1,306 @@
+use {
+    super::{error, pidfd::PidFd, pty::Pty},
+    crate::Result,
+    nix::{
+        fcntl::{self, FcntlArg, OFlag},
+        unistd,
+    },
+    std::{
+        fs::{File, OpenOptions},
+        io::{self, Read, Write},
+        os::unix::{
+            fs::OpenOptionsExt,
+            io::{AsRawFd, FromRawFd, RawFd},
+        },
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::JoinHandle,
+    },
+};
+
+const STDOUT_FN: RawFd = 1;
+const STDERR_FN: RawFd = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Stdio {
+    #[default]
+    Inherit,
+    Null,
+    File(String),   // a path on the host
+    Fd(RawFd),      // kept open by the caller until start() returns
+    Bytes(Vec<u8>), // stdin only
+    Capture {
+        max_bytes: u64, // stdout and stderr only, more output is an OLE
+    },
+    // All streams set to Pty share one terminal, which becomes the controlling
+    // terminal of the container. Its output is not counted for output_limit.
+    Pty,
+}
+
+#[derive(Debug)]
+enum Sink {
+    File(File),
+    Memory(Vec<u8>),
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::File(x) => x.write(buf),
+            Sink::Memory(x) => x.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(x) => x.flush(),
+            Sink::Memory(x) => x.flush(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pump {
+    source: File,
+    sink: Sink,
+    limit: u64,
+}
+
+impl Pump {
+    // Returns whether the limit has been exceeded.
+    fn run(&mut self) -> io::Result<bool> {
+        let mut buf = vec![0_u8; 64 * 1024];
+        let mut written = 0_u64;
+        loop {
+            let n = match self.source.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let allowed = (self.limit - written).min(n as u64) as usize;
+            self.sink.write_all(&buf[..allowed])?;
+            written += allowed as u64;
+            if allowed < n {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn spawn(mut self, pidfd: Arc<PidFd>, exceeded: Arc<AtomicBool>) -> JoinHandle<Sink> {
+        std::thread::spawn(move || {
+            match self.run() {
+                Ok(true) => {
+                    exceeded.store(true, Ordering::SeqCst);
+                    let _ = pidfd.kill();
+                }
+                Ok(false) => {}
+                // a broken sink must not leave the container blocked on a full pipe
+                Err(_) => {
+                    let _ = io::copy(&mut self.source, &mut io::sink());
+                }
+            }
+            self.sink
+        })
+    }
+}
+
+// Everything the parent opens for the standard io of one process. The child
+// only has to dup2(2) the fds it is given, so paths are always host paths.
+// Like every file opened by std, our copies are closed on exec.
+#[derive(Debug)]
+pub struct StdioSetup {
+    child: Vec<(File, RawFd)>, // our copy and its number in the child
+    feed: Option<(File, Vec<u8>)>,
+    stdout: Option<Pump>,
+    stderr: Option<Pump>,
+    pty: Option<Pty>,
+    controlling_tty: Option<RawFd>, // in the child
+}
+
+impl StdioSetup {
+    pub fn new(
+        stdin: &Stdio,
+        stdout: &Stdio,
+        stderr: &Stdio,
+        extra_fds: &[(RawFd, RawFd)],
+        output_limit: Option<u64>,
+    ) -> Result<Self> {
+        let controlling_tty = [stdin, stdout, stderr]
+            .iter()
+            .position(|x| **x == Stdio::Pty)
+            .map(|x| x as RawFd);
+        let (pty, slave) = match controlling_tty {
+            Some(_) => {
+                let (pty, slave) = Pty::open()?;
+                (Some(pty), Some(slave))
+            }
+            None => (None, None),
+        };
+
+        let mut feed = None;
+        let stdin = match stdin {
+            Stdio::Inherit => None,
+            Stdio::Null => Some(File::open("/dev/null")?),
+            Stdio::File(path) => Some(File::open(path)?),
+            Stdio::Fd(fd) => Some(dup_file(*fd, 0)?),
+            Stdio::Bytes(data) => {
+                let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+                feed = Some((unsafe { File::from_raw_fd(write) }, data.clone()));
+                Some(unsafe { File::from_raw_fd(read) })
+            }
+            Stdio::Pty => Some(slave.as_ref().unwrap().try_clone()?),
+            Stdio::Capture { .. } => return Err(error::Error::InvalidStdio.into()),
+        };
+        let (stdout, stdout_pump) = setup_output(stdout, STDOUT_FN, slave.as_ref(), output_limit)?;
+        let (stderr, stderr_pump) = setup_output(stderr, STDERR_FN, slave.as_ref(), output_limit)?;
+
+        let mut child = Vec::new();
+        for (target, file) in [stdin, stdout, stderr].iter_mut().enumerate() {
+            if let Some(file) = file.take() {
+                child.push((file, target as RawFd));
+            }
+        }
+        for (source, target) in extra_fds.iter() {
+            if *target <= STDERR_FN || extra_fds.iter().filter(|x| x.1 == *target).count() > 1 {
+                return Err(error::Error::InvalidExtraFd(*target).into());
+            }
+            child.push((dup_file(*source, 0)?, *target));
+        }
+
+        // Our copies must not be in the way of any dup2(2) in the child.
+        let base = child.iter().map(|x| x.1 + 1).max().unwrap_or(0).max(3);
+        for (file, _) in child.iter_mut() {
+            *file = dup_file(file.as_raw_fd(), base)?;
+        }
+
+        Ok(Self {
+            child,
+            feed,
+            stdout: stdout_pump,
+            stderr: stderr_pump,
+            pty,
+            controlling_tty,
+        })
+    }
+
+    // The fd in the child which has to become its controlling terminal.
+    pub fn controlling_tty(&self) -> Option<RawFd> {
+        self.controlling_tty
+    }
+
+    pub fn take_pty(&mut self) -> Option<Pty> {
+        self.pty.take()
+    }
+
+    // Pairs of (fd of ours, fd in the child), stdio which is not listed
+    // is inherited from us.
+    pub fn child_fds(&self) -> Vec<(RawFd, RawFd)> {
+        self.child
+            .iter()
+            .map(|(file, target)| (file.as_raw_fd(), *target))
+            .collect()
+    }
+
+    // Must be called once the child holds its fds, it closes our copies.
+    pub fn start(self, pidfd: &Arc<PidFd>, exceeded: &Arc<AtomicBool>) -> StdioHandle {
+        let Self {
+            child,
+            feed,
+            stdout,
+            stderr,
+            ..
+        } = self;
+        drop(child);
+
+        StdioHandle {
+            feeder: feed.map(|(mut pipe, data)| {
+                // fails with EPIPE if the process exits without reading it all
+                std::thread::spawn(move || {
+                    let _ = pipe.write_all(&data);
+                })
+            }),
+            stdout: stdout.map(|x| x.spawn(pidfd.clone(), exceeded.clone())),
+            stderr: stderr.map(|x| x.spawn(pidfd.clone(), exceeded.clone())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StdioHandle {
+    feeder: Option<JoinHandle<()>>,
+    stdout: Option<JoinHandle<Sink>>,
+    stderr: Option<JoinHandle<Sink>>,
+}
+
+impl StdioHandle {
+    // Waits until the pipes are closed, that is when every process which has
+    // inherited them is gone. Returns the captured stdout and stderr.
+    pub fn join(self) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+        if let Some(feeder) = self.feeder {
+            let _ = feeder.join();
+        }
+        (join_pump(self.stdout), join_pump(self.stderr))
+    }
+}
+
+fn join_pump(pump: Option<JoinHandle<Sink>>) -> Option<Vec<u8>> {
+    match pump?.join() {
+        Ok(Sink::Memory(data)) => Some(data),
+        _ => None,
+    }
+}
+
+fn dup_file(fd: RawFd, lowest: RawFd) -> Result<File> {
+    let fd = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(lowest))?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn open_output(path: &str) -> Result<File> {
+    Ok(OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o644)
+        .open(path)?)
+}
+
+fn setup_output(
+    stdio: &Stdio,
+    inherited: RawFd,
+    slave: Option<&File>,
+    output_limit: Option<u64>,
+) -> Result<(Option<File>, Option<Pump>)> {
+    let file = match stdio {
+        Stdio::Pty => return Ok((Some(slave.unwrap().try_clone()?), None)),
+        Stdio::Inherit if output_limit.is_none() => return Ok((None, None)),
+        Stdio::Inherit => dup_file(inherited, 0)?,
+        Stdio::Null => open_output("/dev/null")?,
+        Stdio::File(path) => open_output(path)?,
+        Stdio::Fd(fd) => dup_file(*fd, 0)?,
+        Stdio::Capture { max_bytes } => {
+            let limit = output_limit.map_or(*max_bytes, |x| x.min(*max_bytes));
+            return pipe_to(Sink::Memory(Vec::new()), limit);
+        }
+        Stdio::Bytes(_) => return Err(error::Error::InvalidStdio.into()),
+    };
+
+    match output_limit {
+        // counted on its way through a pipe
+        Some(limit) => pipe_to(Sink::File(file), limit),
+        None => Ok((Some(file), None)),
+    }
+}
+
+fn pipe_to(sink: Sink, limit: u64) -> Result<(Option<File>, Option<Pump>)> {
+    let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+    let pump = Pump {
+        source: unsafe { File::from_raw_fd(read) },
+        sink,
+        limit,
+    };
+    Ok((Some(unsafe { File::from_raw_fd(write) }), Some(pump)))
+}
@@ -0,0 +1,91 @@
+use {
+    super::{pidfd::PidFd, result::TimeLimitKind},
+    crate::resource::CGroupLimitPolicy,
+    std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+// The cgroup does not notify us, so its cpu usage is polled at this rate.
+const CPU_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+pub struct Watchdog {
+    uid: u64,
+    cgroup_limits: CGroupLimitPolicy,
+    pidfd: Arc<PidFd>,
+    wall_limit: Duration,
+    cpu_limit: Option<Duration>,
+    started_at: Instant,
+    exceeded: Arc<Mutex<Option<TimeLimitKind>>>,
+}
+
+impl Watchdog {
+    pub fn new(
+        uid: u64,
+        cgroup_limits: CGroupLimitPolicy,
+        pidfd: Arc<PidFd>,
+        wall_limit: Duration,
+        cpu_limit: Option<Duration>,
+        exceeded: Arc<Mutex<Option<TimeLimitKind>>>,
+    ) -> Self {
+        Self {
+            uid,
+            cgroup_limits,
+            pidfd,
+            wall_limit,
+            cpu_limit,
+            started_at: Instant::now(),
+            exceeded,
+        }
+    }
+
+    pub fn pidfd(&self) -> &Arc<PidFd> {
+        &self.pidfd
+    }
+
+    fn exceeded_limit(&self) -> Option<TimeLimitKind> {
+        if let Some(cpu_limit) = self.cpu_limit {
+            let usage = self.cgroup_limits.cpu_usage(self.uid);
+            if let Ok(Some(usage)) = usage {
+                if usage >= cpu_limit {
+                    return Some(TimeLimitKind::Cpu);
+                }
+            }
+        }
+        if self.started_at.elapsed() >= self.wall_limit {
+            return Some(TimeLimitKind::Wall);
+        }
+        None
+    }
+
+    // Kills the process if a limit has been exceeded, otherwise returns how
+    // long to wait for its exit before checking again.
+    pub fn check(&self) -> Option<Duration> {
+        if let Some(kind) = self.exceeded_limit() {
+            *self.exceeded.lock().unwrap() = Some(kind);
+            let _ = self.pidfd.kill();
+            return None;
+        }
+
+        let remaining = self.wall_limit.saturating_sub(self.started_at.elapsed());
+        match self.cpu_limit {
+            Some(_) => Some(remaining.min(CPU_POLL_INTERVAL)),
+            None => Some(remaining),
+        }
+    }
+}
+
+pub fn spawn(watchdog: Watchdog) {
+    // The thread returns as soon as the process exits. It never reaps the
+    // process and never signals by pid, so a reused pid is never killed.
+    std::thread::spawn(move || {
+        while let Some(timeout) = watchdog.check() {
+            match watchdog.pidfd().wait_timeout(timeout) {
+                Ok(false) => continue,
+                _ => return,
+            }
+        }
+    });
+}
@@ -0,0 +1,104 @@
+use {
+    crate::container::{self, EntryFailure, EntryStage},
+    std::{fmt, io},
+};
+
+pub type Source = Box<dyn std::error::Error + Send + Sync>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Every error of the public api, by the subsystem it has happened in. The
+// errors of the setup inside the container arrive as an EntryFailure.
+#[derive(Debug)]
+pub enum Error {
+    IdMap(Source),               // uid_map, gid_map and the subordinate ids
+    Cgroup(Source),              // creating, limiting, reading or removing the cgroup
+    Network(Source),             // what is set up from outside of the namespace
+    Filesystem(Source),          // a MountNamespacedFs
+    Security(Source),            // an ApplySecurityPolicy, e.g. seccomp is unsupported
+    Io(io::Error),               // our own pipes, files and processes
+    Entry(EntryFailure),         // see EntryStage for the failing part
+    Container(container::Error), // a misuse, or the target could not be executed
+}
+
+// Attributes an error to a subsystem, e.g. `mount(...).within(Error::Filesystem)?`.
+// Only needed for errors which can come from anywhere, like those of nix.
+pub trait Within<T> {
+    fn within(self, subsystem: fn(Source) -> Error) -> Result<T>;
+}
+
+impl<T, E: Into<Source>> Within<T> for std::result::Result<T, E> {
+    fn within(self, subsystem: fn(Source) -> Error) -> Result<T> {
+        self.map_err(|e| subsystem(e.into()))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(e: nix::Error) -> Self {
+        Error::Io(io_error(e))
+    }
+}
+
+// Also for where an io::Error is needed, e.g. AsyncFd::try_io().
+pub(crate) fn io_error(e: nix::Error) -> io::Error {
+    match e.as_errno() {
+        Some(errno) => io::Error::from(errno),
+        // e.g. InvalidPath, kept as the source
+        None => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+impl From<cgroups_rs::error::Error> for Error {
+    fn from(e: cgroups_rs::error::Error) -> Self {
+        Error::Cgroup(box e)
+    }
+}
+
+impl From<caps::errors::CapsError> for Error {
+    fn from(e: caps::errors::CapsError) -> Self {
+        Error::Security(box e)
+    }
+}
+
+impl From<container::Error> for Error {
+    fn from(e: container::Error) -> Self {
+        Error::Container(e)
+    }
+}
+
+impl From<EntryFailure> for Error {
+    fn from(failure: EntryFailure) -> Self {
+        match (failure.stage(), failure.errno()) {
+            (EntryStage::Exec, Some(errno)) => {
+                Error::Container(container::Error::ExecFailed(errno))
+            }
+            _ => Error::Entry(failure),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IdMap(x)
+            | Error::Cgroup(x)
+            | Error::Network(x)
+            | Error::Filesystem(x)
+            | Error::Security(x) => Some(x.as_ref()),
+            Error::Io(x) => Some(x),
+            Error::Container(x) => Some(x),
+            Error::Entry(_) => None,
+        }
+    }
+}
@@ -1,15 +1,23 @@
-use nix::mount::{self, MsFlags};
+use {
+    crate::{Error, Result, Within},
+    nix::mount::{self, MsFlags},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "spec", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "spec", serde(rename_all = "snake_case"))]
+pub enum RootMode {
+    #[default]
+    PivotRoot,
+    Chroot, // for roots pivot_root(2) refuses, e.g. an initramfs; escapable with CAP_SYS_CHROOT
+}
 
 pub trait MountNamespacedFs: std::fmt::Debug {
-    fn loading(
-        &self,
-        _base_path: &std::path::Path,
-        _workspace: &std::path::Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn loading(&self, _base_path: &std::path::Path, _workspace: &std::path::Path) -> Result<()> {
         Ok(())
     }
 
-    fn loaded(&self) -> Result<(), Box<dyn std::error::Error>> {
+    fn loaded(&self) -> Result<()> {
         Ok(())
     }
 }
@@ -18,8 +26,9 @@ pub trait MountNamespacedFs: std::fmt::Debug {
 pub struct MountTmpFs;
 
 impl MountNamespacedFs for MountTmpFs {
-    fn loaded(&self) -> Result<(), Box<dyn std::error::Error>> {
-        mount::mount::<_, _, _, str>(Some("tmpfs"), "/tmp", Some("tmpfs"), MsFlags::empty(), None)?;
+    fn loaded(&self) -> Result<()> {
+        mount::mount::<_, _, _, str>(Some("tmpfs"), "/tmp", Some("tmpfs"), MsFlags::empty(), None)
+            .within(Error::Filesystem)?;
         Ok(())
     }
 }
@@ -28,8 +37,9 @@ impl MountNamespacedFs for MountTmpFs {
 pub struct MountProcFs;
 
 impl MountNamespacedFs for MountProcFs {
-    fn loaded(&self) -> Result<(), Box<dyn std::error::Error>> {
-        mount::mount::<_, _, _, str>(Some("proc"), "/proc", Some("proc"), MsFlags::empty(), None)?;
+    fn loaded(&self) -> Result<()> {
+        mount::mount::<_, _, _, str>(Some("proc"), "/proc", Some("proc"), MsFlags::empty(), None)
+            .within(Error::Filesystem)?;
         Ok(())
     }
 }
@@ -46,18 +56,15 @@ impl std::convert::From<String> for MountBindFs {
 }
 
 impl MountNamespacedFs for MountBindFs {
-    fn loading(
-        &self,
-        base_path: &std::path::Path,
-        _: &std::path::Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn loading(&self, base_path: &std::path::Path, _: &std::path::Path) -> Result<()> {
         mount::mount::<str, _, str, str>(
             Some(&self.source),
             base_path,
             None,
             MsFlags::MS_REC | MsFlags::MS_BIND,
             None,
-        )?;
+        )
+        .within(Error::Filesystem)?;
         Ok(())
     }
 }
@@ -74,18 +81,15 @@ impl std::convert::From<String> for MountReadOnlyBindFs {
 }
 
 impl MountNamespacedFs for MountReadOnlyBindFs {
-    fn loading(
-        &self,
-        base_path: &std::path::Path,
-        _: &std::path::Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn loading(&self, base_path: &std::path::Path, _: &std::path::Path) -> Result<()> {
         mount::mount::<str, _, str, str>(
             Some(&self.source),
             base_path,
             None,
             MsFlags::MS_REC | MsFlags::MS_BIND,
             None,
-        )?;
+        )
+        .within(Error::Filesystem)?;
 
         mount::mount::<str, _, str, str>(
             None,
@@ -93,7 +97,8 @@ impl MountNamespacedFs for MountReadOnlyBindFs {
             None,
             MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | MsFlags::MS_REC,
             None,
-        )?;
+        )
+        .within(Error::Filesystem)?;
         Ok(())
     }
 }
@@ -115,17 +120,9 @@ impl std::convert::From<String> for MountExtraFs {
 
 impl MountExtraFs {
     pub fn build(source: String, inner: String) -> Self {
-        let inner = std::path::PathBuf::from(&inner);
-        let inner = inner
-            .strip_prefix("/")
-            .unwrap_or(&inner)
-            .to_string_lossy()
-            .to_owned()
-            .to_string();
-
         Self {
             source: Some(source),
-            inner: inner,
+            inner: relative_to_root(&inner),
         }
     }
 
@@ -135,20 +132,34 @@ impl MountExtraFs {
             inner: "mnt".to_string(),
         }
     }
+
+    // An empty directory of the workspace of its own, mounted at inner.
+    pub fn new_at(inner: String) -> Self {
+        Self {
+            source: None,
+            inner: relative_to_root(&inner),
+        }
+    }
+}
+
+fn relative_to_root(path: &str) -> String {
+    let path = std::path::PathBuf::from(path);
+    path.strip_prefix("/")
+        .unwrap_or(&path)
+        .to_string_lossy()
+        .to_owned()
+        .to_string()
 }
 
 impl MountNamespacedFs for MountExtraFs {
-    fn loading(
-        &self,
-        base_path: &std::path::Path,
-        work_path: &std::path::Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn loading(&self, base_path: &std::path::Path, work_path: &std::path::Path) -> Result<()> {
         let source = match &self.source {
             Some(x) => std::path::PathBuf::from(x),
+            // one per inner path, so that no two mounts share it
             None => {
-                let path = work_path.join("extra");
+                let path = work_path.join("extra").join(&self.inner);
                 if !path.exists() {
-                    std::fs::create_dir_all(&path)?;
+                    std::fs::create_dir_all(&path).within(Error::Filesystem)?;
                 }
                 path
             }
@@ -160,7 +171,8 @@ impl MountNamespacedFs for MountExtraFs {
             None,
             MsFlags::MS_REC | MsFlags::MS_BIND,
             None,
-        )?;
+        )
+        .within(Error::Filesystem)?;
         Ok(())
     }
 }
@@ -198,8 +210,14 @@ impl From<String> for MountSizedTmpFs {
     }
 }
 
+impl MountSizedTmpFs {
+    pub fn build(size_limit: Option<u64>, target: String) -> Self {
+        Self { size_limit, target }
+    }
+}
+
 impl MountNamespacedFs for MountSizedTmpFs {
-    fn loaded(&self) -> Result<(), Box<dyn std::error::Error>> {
+    fn loaded(&self) -> Result<()> {
         let data = if let Some(size) = self.size_limit {
             format!("size={}", size)
         } else {
@@ -212,7 +230,8 @@ impl MountNamespacedFs for MountSizedTmpFs {
             Some("tmpfs"),
             MsFlags::empty(),
             if data.is_empty() { None } else { Some(&data) },
-        )?;
+        )
+        .within(Error::Filesystem)?;
         Ok(())
     }
 }
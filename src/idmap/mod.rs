@@ -1,22 +1,169 @@
-use {crate::VoidResult, std::fs, nix::unistd};
+use {
+    crate::{CommonResult, Result, VoidResult, Within},
+    nix::unistd,
+    std::{fmt, fs, process::Command},
+};
 
-pub fn set_one_map(file: &String, inside_id: u32, outside_id: u32) -> VoidResult {
-    fs::write(file, format!("{} {} 1\n", inside_id, outside_id))?;
-    Ok(())
+#[derive(Debug)]
+pub enum Error {
+    NoSubordinateIds(String),
+    HelperFailed(String, std::process::ExitStatus),
 }
 
-pub fn map_uid_to_root(pid: unistd::Pid) -> VoidResult {
-    set_one_map(&format!("/proc/{}/uid_map", pid), 0, unistd::geteuid().as_raw())?;
-    Ok(())
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "spec", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "spec", serde(deny_unknown_fields))]
+pub struct IdRange {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+impl IdRange {
+    pub fn new(inside: u32, outside: u32, count: u32) -> Self {
+        Self {
+            inside,
+            outside,
+            count,
+        }
+    }
+
+    pub fn contains(&self, inside_id: u32) -> bool {
+        inside_id >= self.inside && inside_id - self.inside < self.count
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "spec", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "spec", serde(default, deny_unknown_fields))]
+pub struct IdMapping {
+    pub uid: Vec<IdRange>,
+    pub gid: Vec<IdRange>,
 }
 
-pub fn map_gid_to_root(pid: unistd::Pid) -> VoidResult {
-    set_one_map(&format!("/proc/{}/gid_map", pid), 0, unistd::getegid().as_raw())?;
+impl IdMapping {
+    // maps a single id inside to the effective ids of the current process
+    pub fn single(inside_uid: u32, inside_gid: u32) -> Self {
+        Self {
+            uid: vec![IdRange::new(inside_uid, unistd::geteuid().as_raw(), 1)],
+            gid: vec![IdRange::new(inside_gid, unistd::getegid().as_raw(), 1)],
+        }
+    }
+
+    // maps root to the current user and 1.. to its subordinate ids
+    pub fn from_subid() -> Result<Self> {
+        let euid = unistd::geteuid();
+        let name = match unistd::User::from_uid(euid).within(crate::Error::IdMap)? {
+            Some(user) => user.name,
+            None => euid.to_string(),
+        };
+
+        let (uid_start, uid_count) =
+            read_subid_file("/etc/subuid", &name, euid.as_raw()).within(crate::Error::IdMap)?;
+        let (gid_start, gid_count) =
+            read_subid_file("/etc/subgid", &name, euid.as_raw()).within(crate::Error::IdMap)?;
+        Ok(Self {
+            uid: vec![
+                IdRange::new(0, euid.as_raw(), 1),
+                IdRange::new(1, uid_start, uid_count),
+            ],
+            gid: vec![
+                IdRange::new(0, unistd::getegid().as_raw(), 1),
+                IdRange::new(1, gid_start, gid_count),
+            ],
+        })
+    }
+
+    pub fn contains_uid(&self, inside_uid: u32) -> bool {
+        self.uid.iter().any(|x| x.contains(inside_uid))
+    }
+
+    pub fn contains_gid(&self, inside_gid: u32) -> bool {
+        self.gid.iter().any(|x| x.contains(inside_gid))
+    }
+
+    fn uid_needs_helper(&self) -> bool {
+        needs_helper(&self.uid, unistd::geteuid().as_raw())
+    }
+
+    fn gid_needs_helper(&self) -> bool {
+        needs_helper(&self.gid, unistd::getegid().as_raw())
+    }
+
+    // An unprivileged process may only write its own gid into gid_map after
+    // "deny" was written to setgroups, setgroups(2) is then refused for good.
+    pub(crate) fn setgroups_allowed(&self) -> bool {
+        unistd::geteuid().is_root() || self.gid_needs_helper()
+    }
+
+    pub(crate) fn apply(&self, pid: unistd::Pid) -> VoidResult {
+        if self.uid_needs_helper() {
+            run_helper("newuidmap", pid, &self.uid)?;
+        } else {
+            set_map(&format!("/proc/{}/uid_map", pid), &self.uid)?;
+        }
+
+        if self.gid_needs_helper() {
+            run_helper("newgidmap", pid, &self.gid)?;
+        } else {
+            if !self.setgroups_allowed() {
+                fs::write(format!("/proc/{}/setgroups", pid), "deny")?;
+            }
+            set_map(&format!("/proc/{}/gid_map", pid), &self.gid)?;
+        }
+        Ok(())
+    }
+}
+
+fn needs_helper(ranges: &[IdRange], own_id: u32) -> bool {
+    if unistd::geteuid().is_root() {
+        return false;
+    }
+    !(ranges.len() == 1 && ranges[0].outside == own_id && ranges[0].count == 1)
+}
+
+fn read_subid_file(file: &str, name: &str, uid: u32) -> CommonResult<(u32, u32)> {
+    let content = fs::read_to_string(file)?;
+    for line in content.lines() {
+        let fields: Vec<&str> = line.trim().split(':').collect();
+        if fields.len() != 3 || (fields[0] != name && fields[0] != uid.to_string()) {
+            continue;
+        }
+        return Ok((fields[1].parse()?, fields[2].parse()?));
+    }
+    Err(box Error::NoSubordinateIds(format!("{} in {}", name, file)))
+}
+
+fn run_helper(helper: &str, pid: unistd::Pid, ranges: &[IdRange]) -> VoidResult {
+    let mut command = Command::new(helper);
+    command.arg(pid.to_string());
+    for range in ranges.iter() {
+        command.arg(range.inside.to_string());
+        command.arg(range.outside.to_string());
+        command.arg(range.count.to_string());
+    }
+
+    let status = command.status()?;
+    if !status.success() {
+        return Err(box Error::HelperFailed(helper.to_string(), status));
+    }
     Ok(())
 }
 
-pub fn map_to_root(pid: unistd::Pid) -> VoidResult {
-    map_uid_to_root(pid)?;
-    map_gid_to_root(pid)?;
+fn set_map(file: &String, ranges: &[IdRange]) -> VoidResult {
+    let mut content = String::new();
+    for range in ranges.iter() {
+        content.push_str(&format!("{} {} {}\n", range.inside, range.outside, range.count));
+    }
+    // the map must be written with a single write(2)
+    fs::write(file, content)?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,96 @@
+use {
+    crate::{
+        container::{Config, Container, RunResult, Stdio},
+        verdict::{self, Verdict},
+        Result,
+    },
+    nix::{fcntl::OFlag, unistd},
+    std::os::unix::io::RawFd,
+};
+
+// A submission and its interactor in two containers, the stdout of each one
+// is the stdin of the other. stdin and stdout of both configs are replaced.
+#[derive(Debug)]
+pub struct Interaction {
+    solution: Container,
+    interactor: Container,
+    pipes: Vec<RawFd>, // our ends, held until both have started
+    failure_codes: Vec<i32>,
+}
+
+// testlib's exit code for a broken checker or test, not a rejected solution
+const TESTLIB_FAIL: i32 = 3;
+
+impl Interaction {
+    pub fn new(mut solution: Config, mut interactor: Config) -> Result<Self> {
+        let (to_interactor_read, to_interactor_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let (to_solution_read, to_solution_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+
+        solution.stdin = Stdio::Fd(to_solution_read);
+        solution.stdout = Stdio::Fd(to_interactor_write);
+        interactor.stdin = Stdio::Fd(to_interactor_read);
+        interactor.stdout = Stdio::Fd(to_solution_write);
+
+        Ok(Self {
+            solution: Container::from(solution),
+            interactor: Container::from(interactor),
+            pipes: vec![
+                to_interactor_read,
+                to_interactor_write,
+                to_solution_read,
+                to_solution_write,
+            ],
+            failure_codes: vec![TESTLIB_FAIL],
+        })
+    }
+
+    // Exit codes of the interactor which are judged as SE instead of WA.
+    pub fn set_failure_codes(&mut self, codes: Vec<i32>) -> &mut Self {
+        self.failure_codes = codes;
+        self
+    }
+
+    pub fn solution(&self) -> &Container {
+        &self.solution
+    }
+
+    pub fn interactor(&self) -> &Container {
+        &self.interactor
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        let res = self.interactor.start().and_then(|_| self.solution.start());
+        // Nobody sees an end of file while we still hold a write end.
+        self.close_pipes();
+        res
+    }
+
+    // Returns the results of the solution and of the interactor.
+    pub fn wait(&mut self) -> Result<(RunResult, RunResult)> {
+        let solution = self.solution.wait()?;
+        let interactor = self.interactor.wait()?;
+        Ok((solution, interactor))
+    }
+
+    pub fn judge(&self) -> Option<Verdict> {
+        verdict::judge_interactive(&self.solution, &self.interactor, &self.failure_codes)
+    }
+
+    pub fn terminate(&mut self) -> Result<()> {
+        self.solution.terminate()?;
+        self.interactor.terminate()?;
+        Ok(())
+    }
+
+    fn close_pipes(&mut self) {
+        for fd in self.pipes.drain(..) {
+            let _ = unistd::close(fd);
+        }
+    }
+}
+
+impl Drop for Interaction {
+    fn drop(&mut self) {
+        self.close_pipes();
+    }
+}
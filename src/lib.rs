@@ -9,11 +9,19 @@ extern crate libscmp;
 extern crate caps;
 extern crate cgroups_rs;
 
+mod error;
 pub mod container;
 pub mod filesystem;
+pub mod network;
 pub mod security;
 pub mod resource;
-mod idmap;
+pub mod verdict;
+pub mod idmap;
+pub mod interactive;
+#[cfg(feature = "spec")]
+pub mod spec;
 
-type CommonResult<T> = Result<T, Box<dyn std::error::Error>>;
+pub use error::{Error, Result, Source, Within};
+
+type CommonResult<T> = std::result::Result<T, Source>;
 type VoidResult = CommonResult<()>;
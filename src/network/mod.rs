@@ -0,0 +1,212 @@
+use {
+    crate::VoidResult,
+    nix::{
+        errno::Errno,
+        libc,
+        sched::CloneFlags,
+        sys::socket::{self, AddressFamily, SockFlag, SockType},
+        unistd::{self, Pid},
+    },
+    std::{fmt, net::Ipv4Addr, os::unix::io::RawFd, process::Command},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    CommandFailed(String, std::process::ExitStatus),
+    SubnetExhausted,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum NetworkMode {
+    #[default]
+    Loopback, // a fresh network namespace with only lo brought up
+    Host, // shares the network of the host, for trusted steps only
+    Veth(NetworkConfig),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkConfig {
+    pub bridge: String, // must already exist on the host
+    pub subnet: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>, // defaults to the first address of subnet
+    pub address: Option<Ipv4Addr>, // defaults to an address picked by Config::uid
+    pub inner_name: String,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            bridge: "ssandbox0".to_string(),
+            subnet: Ipv4Addr::new(10, 88, 0, 0),
+            prefix_len: 16,
+            gateway: None,
+            address: None,
+            inner_name: "eth0".to_string(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    fn host_mask(&self) -> u32 {
+        if self.prefix_len >= 32 {
+            0
+        } else {
+            u32::MAX >> self.prefix_len
+        }
+    }
+
+    pub fn gateway_address(&self) -> Ipv4Addr {
+        match self.gateway {
+            Some(x) => x,
+            None => Ipv4Addr::from((u32::from(self.subnet) & !self.host_mask()) + 1),
+        }
+    }
+
+    // Addresses picked by uid may collide, set address explicitly if the
+    // bridge is shared by many containers at once.
+    pub fn container_address(&self, uid: u64) -> Result<Ipv4Addr, Error> {
+        if let Some(x) = self.address {
+            return Ok(x);
+        }
+
+        let mask = self.host_mask();
+        // network, gateway and broadcast addresses are never picked
+        if mask < 4 {
+            return Err(Error::SubnetExhausted);
+        }
+        let host = 2 + (uid % (mask as u64 - 2)) as u32;
+        let address = Ipv4Addr::from((u32::from(self.subnet) & !mask) | host);
+        if address == self.gateway_address() {
+            return Ok(Ipv4Addr::from(u32::from(address) + 1));
+        }
+        Ok(address)
+    }
+
+    pub fn host_name(&self, uid: u64) -> String {
+        // interface names are limited to 15 bytes
+        format!("vsb{:012x}", uid & 0xffff_ffff_ffff)
+    }
+}
+
+impl NetworkMode {
+    // called by the parent after the container has been cloned
+    pub(crate) fn setup_outside(&self, uid: u64, pid: Pid) -> VoidResult {
+        let config = match self {
+            NetworkMode::Veth(x) => x,
+            _ => return Ok(()),
+        };
+
+        let host_name = config.host_name(uid);
+        let address = format!("{}/{}", config.container_address(uid)?, config.prefix_len);
+        let gateway = config.gateway_address().to_string();
+        let inner = config.inner_name.as_str();
+        let pid = pid.to_string();
+
+        run_command(
+            "ip",
+            &[
+                "link", "add", &host_name, "type", "veth", "peer", "name", inner, "netns", &pid,
+            ],
+        )?;
+        run_command("ip", &["link", "set", &host_name, "master", &config.bridge])?;
+        run_command("ip", &["link", "set", &host_name, "up"])?;
+
+        // the inner end is configured from here, the root image may lack ip(8)
+        run_command(
+            "nsenter",
+            &[
+                "-t", &pid, "-n", "ip", "addr", "add", &address, "dev", inner,
+            ],
+        )?;
+        run_command(
+            "nsenter",
+            &["-t", &pid, "-n", "ip", "link", "set", inner, "up"],
+        )?;
+        run_command(
+            "nsenter",
+            &[
+                "-t", &pid, "-n", "ip", "route", "add", "default", "via", &gateway,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn teardown(&self, uid: u64) -> VoidResult {
+        if let NetworkMode::Veth(config) = self {
+            let host_name = config.host_name(uid);
+            // the pair is destroyed with the namespace if the container is gone
+            if std::path::Path::new("/sys/class/net")
+                .join(&host_name)
+                .exists()
+            {
+                run_command("ip", &["link", "del", &host_name])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn clone_flags(&self) -> CloneFlags {
+        match self {
+            NetworkMode::Host => CloneFlags::empty(),
+            _ => CloneFlags::CLONE_NEWNET,
+        }
+    }
+
+    // called inside the container, before capabilities are dropped
+    pub(crate) fn setup_inside(&self) -> VoidResult {
+        match self {
+            NetworkMode::Host => Ok(()),
+            _ => set_link_up("lo"),
+        }
+    }
+}
+
+fn run_command(program: &str, args: &[&str]) -> VoidResult {
+    let status = Command::new(program).args(args).status()?;
+    if !status.success() {
+        return Err(box Error::CommandFailed(
+            format!("{} {}", program, args.join(" ")),
+            status,
+        ));
+    }
+    Ok(())
+}
+
+fn make_ifreq(name: &str) -> libc::ifreq {
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (i, byte) in name.bytes().take(libc::IFNAMSIZ - 1).enumerate() {
+        req.ifr_name[i] = byte as libc::c_char;
+    }
+    req
+}
+
+fn do_set_link_up(sock: RawFd, name: &str) -> VoidResult {
+    let mut req = make_ifreq(name);
+    Errno::result(unsafe { libc::ioctl(sock, libc::SIOCGIFFLAGS, &mut req) })?;
+    unsafe {
+        req.ifr_ifru.ifru_flags |= (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+    }
+    Errno::result(unsafe { libc::ioctl(sock, libc::SIOCSIFFLAGS, &req) })?;
+    Ok(())
+}
+
+pub(crate) fn set_link_up(name: &str) -> VoidResult {
+    let sock = socket::socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    let res = do_set_link_up(sock, name);
+    unistd::close(sock)?;
+    res
+}
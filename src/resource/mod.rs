@@ -1,15 +1,31 @@
 use {
-    crate::VoidResult,
+    crate::{Result, Within},
     cgroups_rs::{
-        cpu::CpuController, freezer::FreezerController, memory::MemController, pid::PidController,
-        MaxValue,
+        cgroup::Cgroup, cpu::CpuController, cpuacct::CpuAcctController, freezer::FreezerController,
+        memory::MemController, pid::PidController, Controller, MaxValue,
     },
+    std::{fmt, time::Duration},
 };
 
+#[derive(Debug)]
+pub enum Error {
+    CpuAccountingUnavailable, // a cpu time limit needs the cpu or cpuacct controller
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "spec", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "spec", serde(default, deny_unknown_fields))]
 pub struct CGroupLimitPolicy {
     cpu_limit: Option<i64>,
-    time_limit: Option<u64>,
+    time_limit: Option<u64>, // cpu time of the whole cgroup in milliseconds
     memory_limit: Option<i64>,
     fork_limit: Option<u32>,
 }
@@ -35,31 +51,48 @@ impl CGroupLimitPolicy {
         self
     }
 
+    pub fn time_limit(&self) -> Option<Duration> {
+        self.time_limit.map(Duration::from_millis)
+    }
+
+    pub fn memory_limit(&self) -> Option<i64> {
+        self.memory_limit
+    }
+
+    pub fn fork_limit(&self) -> Option<u32> {
+        self.fork_limit
+    }
+
     pub fn clear_time_limit(&mut self) -> &mut Self {
         self.time_limit = None;
         self
     }
 
     pub fn clear_memory_limit(&mut self) -> &mut Self {
-        self.time_limit = None;
+        self.memory_limit = None;
         self
     }
 
     pub fn clear_fork_limit(&mut self) -> &mut Self {
-        self.time_limit = None;
+        self.fork_limit = None;
         self
     }
 
     pub fn clear_cpu_limit(&mut self) -> &mut Self {
-        self.time_limit = None;
+        self.cpu_limit = None;
         self
     }
 
-    pub fn apply(&self, uid: u64, pid: nix::unistd::Pid) -> VoidResult {
+    pub fn apply(&self, uid: u64, pid: nix::unistd::Pid) -> Result<()> {
         let hier = cgroups_rs::hierarchies::auto();
         let cg = cgroups_rs::cgroup::Cgroup::new(hier, &format!("ssandbox.rs.container.{}", uid));
         cg.add_task(cgroups_rs::CgroupPid::from(pid.as_raw() as u64))?;
 
+        // the time limit is enforced by polling, so it must be readable
+        if self.time_limit.is_some() && read_cpu_usage(&cg)?.is_none() {
+            return Err(crate::Error::Cgroup(box Error::CpuAccountingUnavailable));
+        }
+
         if let Some(fork_limit) = self.fork_limit {
             let control: Option<&PidController> = cg.controller_of();
             if let Some(control) = control {
@@ -87,7 +120,14 @@ impl CGroupLimitPolicy {
         Ok(())
     }
 
-    pub fn freeze(&self, uid: u64) -> VoidResult {
+    pub fn add_task(&self, uid: u64, pid: nix::unistd::Pid) -> Result<()> {
+        let hier = cgroups_rs::hierarchies::auto();
+        let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
+        cg.add_task(cgroups_rs::CgroupPid::from(pid.as_raw() as u64))?;
+        Ok(())
+    }
+
+    pub fn freeze(&self, uid: u64) -> Result<()> {
         let hier = cgroups_rs::hierarchies::auto();
         let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
         let control: Option<&FreezerController> = cg.controller_of();
@@ -97,7 +137,7 @@ impl CGroupLimitPolicy {
         Ok(())
     }
 
-    pub fn thaw(&self, uid: u64) -> VoidResult {
+    pub fn thaw(&self, uid: u64) -> Result<()> {
         let hier = cgroups_rs::hierarchies::auto();
         let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
         let control: Option<&FreezerController> = cg.controller_of();
@@ -107,10 +147,92 @@ impl CGroupLimitPolicy {
         Ok(())
     }
 
-    pub fn delete(&self, uid: u64) -> VoidResult {
+    pub fn peak_memory(&self, uid: u64) -> Result<Option<u64>> {
         let hier = cgroups_rs::hierarchies::auto();
         let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
+        let control: Option<&MemController> = cg.controller_of();
+        if let Some(control) = control {
+            if control.v2() {
+                // memory.peak is only available since linux 5.19
+                let peak = std::fs::read_to_string(control.path().join("memory.peak"))
+                    .within(crate::Error::Cgroup)?;
+                return Ok(Some(peak.trim().parse().within(crate::Error::Cgroup)?));
+            }
+            return Ok(Some(control.memory_stat().max_usage_in_bytes));
+        }
+        Ok(None)
+    }
+
+    pub fn oom_kill_count(&self, uid: u64) -> Result<u64> {
+        let hier = cgroups_rs::hierarchies::auto();
+        let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
+        let control: Option<&MemController> = cg.controller_of();
+        if let Some(control) = control {
+            if control.v2() {
+                let events = std::fs::read_to_string(control.path().join("memory.events"))
+                    .within(crate::Error::Cgroup)?;
+                for line in events.lines() {
+                    let mut fields = line.split_whitespace();
+                    if fields.next() == Some("oom_kill") {
+                        let count = fields.next().unwrap_or("0").parse();
+                        return count.within(crate::Error::Cgroup);
+                    }
+                }
+                return Ok(0);
+            }
+            return Ok(control.memory_stat().oom_control.oom_kill);
+        }
+        Ok(0)
+    }
+
+    pub fn refused_fork_count(&self, uid: u64) -> Result<u64> {
+        let hier = cgroups_rs::hierarchies::auto();
+        let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
+        let control: Option<&PidController> = cg.controller_of();
+        if let Some(control) = control {
+            return Ok(control.get_pid_events()?);
+        }
+        Ok(0)
+    }
+
+    pub fn cpu_usage(&self, uid: u64) -> Result<Option<Duration>> {
+        let hier = cgroups_rs::hierarchies::auto();
+        let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
+        read_cpu_usage(&cg)
+    }
+
+    pub fn delete(&self, uid: u64) -> Result<()> {
+        let hier = cgroups_rs::hierarchies::auto();
+        let name = format!("ssandbox.rs.container.{}", uid);
+        // v1 tolerates a cgroup which has already been deleted, v2 does not
+        if hier.v2() && !hier.root().join(&name).exists() {
+            return Ok(());
+        }
+        let cg = cgroups_rs::cgroup::Cgroup::load(hier, &name);
         cg.delete()?;
         Ok(())
     }
 }
+
+// Cpu time summed over every process that has ever been in the cgroup.
+fn read_cpu_usage(cg: &Cgroup) -> Result<Option<Duration>> {
+    let control: Option<&CpuController> = cg.controller_of();
+    if let Some(control) = control {
+        if control.v2() {
+            for line in control.cpu().stat.lines() {
+                let mut fields = line.split_whitespace();
+                if fields.next() == Some("usage_usec") {
+                    let usage = fields.next().unwrap_or("0").parse();
+                    let usage = usage.within(crate::Error::Cgroup)?;
+                    return Ok(Some(Duration::from_micros(usage)));
+                }
+            }
+        }
+    }
+
+    let control: Option<&CpuAcctController> = cg.controller_of();
+    if let Some(control) = control {
+        return Ok(Some(Duration::from_nanos(control.cpuacct().usage)));
+    }
+    Ok(None)
+}
@@ -1,4 +1,4 @@
-use {crate::VoidResult, super::ApplySecurityPolicy, caps::CapsHashSet};
+use {crate::Result, super::ApplySecurityPolicy, caps::CapsHashSet};
 
 #[derive(Debug, Clone)]
 pub struct CapabilityPolicy {
@@ -46,8 +46,10 @@ impl Default for CapabilityPolicy {
     }
 }
 
+// Limits every set, the bounding one too, which becomes the permitted set of
+// a target running as root on execve(2).
 impl ApplySecurityPolicy for CapabilityPolicy {
-    fn apply(&self) -> VoidResult {        
+    fn apply(&self) -> Result<()> {        
         let allowed = self.get();
         let mut ok_caps = CapsHashSet::new();
         for item in allowed.iter() {
@@ -55,9 +57,15 @@ impl ApplySecurityPolicy for CapabilityPolicy {
                 ok_caps.insert(item.clone());
             }
         }
-        
+
+        // needs CAP_SETPCAP, so before the effective set is limited
+        for item in caps::read(None, caps::CapSet::Bounding)?.difference(&ok_caps) {
+            caps::drop(None, caps::CapSet::Bounding, *item)?;
+        }
+        caps::clear(None, caps::CapSet::Ambient)?;
         caps::set(None, caps::CapSet::Inheritable, &ok_caps)?;
         caps::set(None, caps::CapSet::Effective, &ok_caps)?;
+        caps::set(None, caps::CapSet::Permitted, &ok_caps)?;
         Ok(())
     }
 }
@@ -1,11 +1,13 @@
-use {crate::VoidResult};
+use {crate::Result};
 
 pub mod cap;
+pub mod rlimit;
 pub mod seccomp;
 
 pub trait ApplySecurityPolicy: std::fmt::Debug {
-    fn apply(&self) -> VoidResult;
+    fn apply(&self) -> Result<()>;
 }
 
 pub use cap::CapabilityPolicy;
+pub use rlimit::{Resource, Rlimit, RlimitPolicy, RlimitValue};
 pub use seccomp::SeccompPolicy;
\ No newline at end of file
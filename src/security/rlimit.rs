@@ -0,0 +1,173 @@
+use {
+    super::ApplySecurityPolicy,
+    crate::{Result, Within},
+    nix::{errno::Errno, libc},
+    std::{collections::BTreeMap, fmt},
+};
+
+#[cfg(target_env = "gnu")]
+type RawResource = libc::__rlimit_resource_t;
+#[cfg(not(target_env = "gnu"))]
+type RawResource = libc::c_int;
+
+#[derive(Debug)]
+pub enum Error {
+    SoftAboveHard(Resource),
+    AboveParentHardLimit(Resource, RlimitValue),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Resource {
+    AddressSpace, // in bytes
+    CoreSize,     // in bytes
+    CpuTime,      // in seconds, per process, the target only dies at the hard limit
+    DataSize,     // in bytes
+    FileSize,     // in bytes
+    LockedMemory, // in bytes
+    MessageQueue, // in bytes of POSIX message queues, counted per real uid
+    Nice,         // the nice value may be raised up to 20 - limit
+    OpenFiles,
+    PendingSignals, // counted per real uid
+    Processes,      // counted per real uid, not per container
+    RealtimePriority,
+    RealtimeTime, // in microseconds of cpu time without a blocking syscall
+    ResidentSet,  // in bytes, ignored by linux since 2.6
+    StackSize,    // in bytes
+}
+
+impl Resource {
+    fn raw(self) -> RawResource {
+        match self {
+            Resource::AddressSpace => libc::RLIMIT_AS,
+            Resource::CoreSize => libc::RLIMIT_CORE,
+            Resource::CpuTime => libc::RLIMIT_CPU,
+            Resource::DataSize => libc::RLIMIT_DATA,
+            Resource::FileSize => libc::RLIMIT_FSIZE,
+            Resource::LockedMemory => libc::RLIMIT_MEMLOCK,
+            Resource::MessageQueue => libc::RLIMIT_MSGQUEUE,
+            Resource::Nice => libc::RLIMIT_NICE,
+            Resource::OpenFiles => libc::RLIMIT_NOFILE,
+            Resource::PendingSignals => libc::RLIMIT_SIGPENDING,
+            Resource::Processes => libc::RLIMIT_NPROC,
+            Resource::RealtimePriority => libc::RLIMIT_RTPRIO,
+            Resource::RealtimeTime => libc::RLIMIT_RTTIME,
+            Resource::ResidentSet => libc::RLIMIT_RSS,
+            Resource::StackSize => libc::RLIMIT_STACK,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RlimitValue {
+    Limited(u64),
+    Unlimited,
+}
+
+impl RlimitValue {
+    fn from_raw(value: libc::rlim_t) -> Self {
+        match value {
+            libc::RLIM_INFINITY => RlimitValue::Unlimited,
+            x => RlimitValue::Limited(x),
+        }
+    }
+
+    fn raw(self) -> libc::rlim_t {
+        match self {
+            RlimitValue::Limited(x) => x,
+            RlimitValue::Unlimited => libc::RLIM_INFINITY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rlimit {
+    pub soft: RlimitValue,
+    pub hard: RlimitValue,
+}
+
+impl Rlimit {
+    pub fn new(soft: RlimitValue, hard: RlimitValue) -> Self {
+        Self { soft, hard }
+    }
+
+    pub fn fixed(value: RlimitValue) -> Self {
+        Self::new(value, value)
+    }
+
+    pub fn of_current_process(resource: Resource) -> Result<Self> {
+        let mut raw = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        Errno::result(unsafe { libc::getrlimit(resource.raw(), &mut raw) })
+            .within(crate::Error::Security)?;
+        Ok(Self::new(
+            RlimitValue::from_raw(raw.rlim_cur),
+            RlimitValue::from_raw(raw.rlim_max),
+        ))
+    }
+}
+
+// Resources that are not set keep the limits inherited from the parent.
+#[derive(Debug, Clone, Default)]
+pub struct RlimitPolicy {
+    limits: BTreeMap<Resource, Rlimit>,
+}
+
+impl RlimitPolicy {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn set(&mut self, resource: Resource, limit: Rlimit) -> &mut Self {
+        self.limits.insert(resource, limit);
+        self
+    }
+
+    pub fn get(&self, resource: Resource) -> Option<Rlimit> {
+        self.limits.get(&resource).copied()
+    }
+
+    pub fn clear(&mut self, resource: Resource) -> &mut Self {
+        self.limits.remove(&resource);
+        self
+    }
+
+    // Raising a hard limit needs CAP_SYS_RESOURCE in the initial user
+    // namespace, which the container never has, so check it in the parent.
+    pub fn validate(&self) -> Result<()> {
+        for (resource, limit) in self.limits.iter() {
+            if limit.soft > limit.hard {
+                return Err(crate::Error::Security(box Error::SoftAboveHard(*resource)));
+            }
+            let parent = Rlimit::of_current_process(*resource)?;
+            if limit.hard > parent.hard {
+                let e = Error::AboveParentHardLimit(*resource, parent.hard);
+                return Err(crate::Error::Security(box e));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ApplySecurityPolicy for RlimitPolicy {
+    fn apply(&self) -> Result<()> {
+        for (resource, limit) in self.limits.iter() {
+            let raw = libc::rlimit {
+                rlim_cur: limit.soft.raw(),
+                rlim_max: limit.hard.raw(),
+            };
+            Errno::result(unsafe { libc::setrlimit(resource.raw(), &raw) })
+                .within(crate::Error::Security)?;
+        }
+        Ok(())
+    }
+}
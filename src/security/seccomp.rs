@@ -1,4 +1,7 @@
-use {super::ApplySecurityPolicy, crate::VoidResult};
+use {
+    super::ApplySecurityPolicy,
+    crate::{Error, Result, Within},
+};
 
 #[derive(Debug, Clone)]
 pub struct SeccompPolicy {
@@ -10,23 +13,25 @@ fn common_apply(
     target: &Vec<String>,
     default_action: libscmp::Action,
     matched_action: libscmp::Action,
-) -> VoidResult {
+) -> Result<()> {
     if target.len() == 0 {
         return Ok(());
     }
 
-    let mut filter = libscmp::Filter::new(default_action)?;
+    let mut filter = libscmp::Filter::new(default_action).within(Error::Security)?;
     for call_name in target.iter() {
         let call_id = libscmp::resolve_syscall_name(call_name);
         match call_id {
             Some(call_id) if call_id >= 0 => {
-                filter.add_rule_exact(matched_action, call_id, &[])?;
+                filter
+                    .add_rule_exact(matched_action, call_id, &[])
+                    .within(Error::Security)?;
             }
             _ => {}
         };
     }
 
-    filter.load()?;
+    filter.load().within(Error::Security)?;
     Ok(())
 }
 
@@ -38,7 +43,7 @@ impl SeccompPolicy {
         }
     }
 
-    fn apply_as_whitelist(&self) -> VoidResult {
+    fn apply_as_whitelist(&self) -> Result<()> {
         use libscmp::Action;
         common_apply(
             &self.allow,
@@ -47,7 +52,7 @@ impl SeccompPolicy {
         )
     }
 
-    fn apply_as_blacklist(&self) -> VoidResult {
+    fn apply_as_blacklist(&self) -> Result<()> {
         use libscmp::Action;
         common_apply(
             &self.deny,
@@ -93,7 +98,7 @@ impl Default for SeccompPolicy {
 }
 
 impl ApplySecurityPolicy for SeccompPolicy {
-    fn apply(&self) -> VoidResult {
+    fn apply(&self) -> Result<()> {
         self.apply_as_whitelist()?;
         self.apply_as_blacklist()
     }
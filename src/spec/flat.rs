@@ -0,0 +1,532 @@
+use {
+    super::{LimitSpec, MountSpec, PolicySpec, StdioSpec},
+    crate::{
+        network::{NetworkConfig, NetworkMode},
+        security::{Resource, RlimitValue},
+    },
+    caps::Capability,
+    serde::{de, Deserialize, Deserializer, Serialize, Serializer},
+    std::{convert::TryFrom, fmt, marker::PhantomData, net::Ipv4Addr, os::unix::io::RawFd},
+};
+
+// The tagged entries of a spec are read as flat structs with every field of
+// every type, then checked against their type. serde buffers the content of
+// an internally tagged enum, which loses the path to a wrong field, and
+// accepts any field for a type without fields.
+
+fn unused_field(set: &[(&str, bool)], used: &[&str]) -> Result<(), String> {
+    match set.iter().find(|(name, set)| *set && !used.contains(name)) {
+        Some((name, _)) => Err(format!("field `{}` does not apply to this type", name)),
+        None => Ok(()),
+    }
+}
+
+fn required<T>(value: Option<T>, name: &str) -> Result<T, String> {
+    value.ok_or_else(|| format!("missing field `{}`", name))
+}
+
+// Reads the flat struct F and checks it against its type. Unlike with
+// #[serde(try_from)], the check fails while the deserializer of the entry is
+// still running, so that toml gives the position of the entry, not that of
+// the array it is in.
+fn deserialize_checked<'de, D, F, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    F: Deserialize<'de>,
+    T: TryFrom<F, Error = String>,
+{
+    struct CheckedVisitor<F, T>(PhantomData<(F, T)>);
+
+    impl<'de, F: Deserialize<'de>, T: TryFrom<F, Error = String>> de::Visitor<'de>
+        for CheckedVisitor<F, T>
+    {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a table with a type")
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<T, A::Error> {
+            let fields = F::deserialize(de::value::MapAccessDeserializer::new(map))?;
+            T::try_from(fields).map_err(de::Error::custom)
+        }
+    }
+
+    deserializer.deserialize_map(CheckedVisitor(PhantomData))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum MountKind {
+    Tmp,
+    Proc,
+    Bind,
+    ReadOnlyBind,
+    Extra,
+    SizedTmp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct MountFields {
+    #[serde(rename = "type")]
+    kind: MountKind,
+    source: Option<String>,
+    inner: Option<String>,
+    size_limit: Option<u64>,
+    target: Option<String>,
+}
+
+impl TryFrom<MountFields> for MountSpec {
+    type Error = String;
+
+    fn try_from(x: MountFields) -> Result<Self, String> {
+        let used: &[&str] = match x.kind {
+            MountKind::Tmp | MountKind::Proc => &[],
+            MountKind::Bind | MountKind::ReadOnlyBind => &["source"],
+            MountKind::Extra => &["source", "inner"],
+            MountKind::SizedTmp => &["size_limit", "target"],
+        };
+        let set = [
+            ("source", x.source.is_some()),
+            ("inner", x.inner.is_some()),
+            ("size_limit", x.size_limit.is_some()),
+            ("target", x.target.is_some()),
+        ];
+        unused_field(&set, used)?;
+
+        Ok(match x.kind {
+            MountKind::Tmp => MountSpec::Tmp,
+            MountKind::Proc => MountSpec::Proc,
+            MountKind::Bind => MountSpec::Bind {
+                source: required(x.source, "source")?,
+            },
+            MountKind::ReadOnlyBind => MountSpec::ReadOnlyBind {
+                source: required(x.source, "source")?,
+            },
+            MountKind::Extra => MountSpec::Extra {
+                source: x.source,
+                inner: x.inner,
+            },
+            MountKind::SizedTmp => MountSpec::SizedTmp {
+                size_limit: x.size_limit,
+                target: x.target,
+            },
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for MountSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_checked::<D, MountFields, _>(deserializer)
+    }
+}
+
+impl From<MountSpec> for MountFields {
+    fn from(spec: MountSpec) -> Self {
+        let mut x = MountFields {
+            kind: MountKind::Tmp,
+            source: None,
+            inner: None,
+            size_limit: None,
+            target: None,
+        };
+        match spec {
+            MountSpec::Tmp => {}
+            MountSpec::Proc => x.kind = MountKind::Proc,
+            MountSpec::Bind { source } => {
+                x.kind = MountKind::Bind;
+                x.source = Some(source);
+            }
+            MountSpec::ReadOnlyBind { source } => {
+                x.kind = MountKind::ReadOnlyBind;
+                x.source = Some(source);
+            }
+            MountSpec::Extra { source, inner } => {
+                x.kind = MountKind::Extra;
+                x.source = source;
+                x.inner = inner;
+            }
+            MountSpec::SizedTmp { size_limit, target } => {
+                x.kind = MountKind::SizedTmp;
+                x.size_limit = size_limit;
+                x.target = target;
+            }
+        }
+        x
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum PolicyKind {
+    Capability,
+    Seccomp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct PolicyFields {
+    #[serde(rename = "type")]
+    kind: PolicyKind,
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+}
+
+fn parse_capabilities(names: Option<Vec<String>>) -> Result<Option<Vec<Capability>>, String> {
+    let names = match names {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let mut capabilities = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        match name.parse() {
+            Ok(x) => capabilities.push(x),
+            Err(_) => return Err(format!("unknown capability `{}`", name)),
+        }
+    }
+    Ok(Some(capabilities))
+}
+
+impl TryFrom<PolicyFields> for PolicySpec {
+    type Error = String;
+
+    fn try_from(x: PolicyFields) -> Result<Self, String> {
+        Ok(match x.kind {
+            PolicyKind::Capability => PolicySpec::Capability {
+                allow: parse_capabilities(x.allow)?,
+                deny: parse_capabilities(x.deny)?,
+            },
+            PolicyKind::Seccomp => PolicySpec::Seccomp {
+                allow: x.allow,
+                deny: x.deny,
+            },
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for PolicySpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_checked::<D, PolicyFields, _>(deserializer)
+    }
+}
+
+impl From<PolicySpec> for PolicyFields {
+    fn from(spec: PolicySpec) -> Self {
+        let names = |x: Option<Vec<Capability>>| -> Option<Vec<String>> {
+            x.map(|x| x.iter().map(|x| x.to_string()).collect())
+        };
+        match spec {
+            PolicySpec::Capability { allow, deny } => PolicyFields {
+                kind: PolicyKind::Capability,
+                allow: names(allow),
+                deny: names(deny),
+            },
+            PolicySpec::Seccomp { allow, deny } => PolicyFields {
+                kind: PolicyKind::Seccomp,
+                allow,
+                deny,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum StdioKind {
+    Inherit,
+    Null,
+    File,
+    Fd,
+    Bytes,
+    Capture,
+    Pty,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct StdioFields {
+    #[serde(rename = "type")]
+    kind: StdioKind,
+    path: Option<String>,
+    fd: Option<RawFd>,
+    data: Option<String>,
+    max_bytes: Option<u64>,
+}
+
+impl TryFrom<StdioFields> for StdioSpec {
+    type Error = String;
+
+    fn try_from(x: StdioFields) -> Result<Self, String> {
+        let used: &[&str] = match x.kind {
+            StdioKind::Inherit | StdioKind::Null | StdioKind::Pty => &[],
+            StdioKind::File => &["path"],
+            StdioKind::Fd => &["fd"],
+            StdioKind::Bytes => &["data"],
+            StdioKind::Capture => &["max_bytes"],
+        };
+        let set = [
+            ("path", x.path.is_some()),
+            ("fd", x.fd.is_some()),
+            ("data", x.data.is_some()),
+            ("max_bytes", x.max_bytes.is_some()),
+        ];
+        unused_field(&set, used)?;
+
+        Ok(match x.kind {
+            StdioKind::Inherit => StdioSpec::Inherit,
+            StdioKind::Null => StdioSpec::Null,
+            StdioKind::File => StdioSpec::File(required(x.path, "path")?),
+            StdioKind::Fd => StdioSpec::Fd(required(x.fd, "fd")?),
+            StdioKind::Bytes => StdioSpec::Bytes(required(x.data, "data")?),
+            StdioKind::Capture => StdioSpec::Capture {
+                max_bytes: required(x.max_bytes, "max_bytes")?,
+            },
+            StdioKind::Pty => StdioSpec::Pty,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for StdioSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_checked::<D, StdioFields, _>(deserializer)
+    }
+}
+
+impl From<StdioSpec> for StdioFields {
+    fn from(spec: StdioSpec) -> Self {
+        let mut x = StdioFields {
+            kind: StdioKind::Inherit,
+            path: None,
+            fd: None,
+            data: None,
+            max_bytes: None,
+        };
+        match spec {
+            StdioSpec::Inherit => {}
+            StdioSpec::Null => x.kind = StdioKind::Null,
+            StdioSpec::File(path) => {
+                x.kind = StdioKind::File;
+                x.path = Some(path);
+            }
+            StdioSpec::Fd(fd) => {
+                x.kind = StdioKind::Fd;
+                x.fd = Some(fd);
+            }
+            StdioSpec::Bytes(data) => {
+                x.kind = StdioKind::Bytes;
+                x.data = Some(data);
+            }
+            StdioSpec::Capture { max_bytes } => {
+                x.kind = StdioKind::Capture;
+                x.max_bytes = Some(max_bytes);
+            }
+            StdioSpec::Pty => x.kind = StdioKind::Pty,
+        }
+        x
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NetworkKind {
+    Loopback,
+    Host,
+    Veth,
+}
+
+// Fields of veth which are left out keep the value of NetworkConfig::default().
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NetworkFields {
+    #[serde(rename = "type")]
+    kind: NetworkKind,
+    bridge: Option<String>,
+    subnet: Option<Ipv4Addr>,
+    prefix_len: Option<u8>,
+    gateway: Option<Ipv4Addr>,
+    address: Option<Ipv4Addr>,
+    inner_name: Option<String>,
+}
+
+impl TryFrom<NetworkFields> for NetworkMode {
+    type Error = String;
+
+    fn try_from(x: NetworkFields) -> Result<Self, String> {
+        let used: &[&str] = match x.kind {
+            NetworkKind::Loopback | NetworkKind::Host => &[],
+            NetworkKind::Veth => &[
+                "bridge",
+                "subnet",
+                "prefix_len",
+                "gateway",
+                "address",
+                "inner_name",
+            ],
+        };
+        let set = [
+            ("bridge", x.bridge.is_some()),
+            ("subnet", x.subnet.is_some()),
+            ("prefix_len", x.prefix_len.is_some()),
+            ("gateway", x.gateway.is_some()),
+            ("address", x.address.is_some()),
+            ("inner_name", x.inner_name.is_some()),
+        ];
+        unused_field(&set, used)?;
+
+        Ok(match x.kind {
+            NetworkKind::Loopback => NetworkMode::Loopback,
+            NetworkKind::Host => NetworkMode::Host,
+            NetworkKind::Veth => {
+                let default = NetworkConfig::default();
+                NetworkMode::Veth(NetworkConfig {
+                    bridge: x.bridge.unwrap_or(default.bridge),
+                    subnet: x.subnet.unwrap_or(default.subnet),
+                    prefix_len: x.prefix_len.unwrap_or(default.prefix_len),
+                    gateway: x.gateway,
+                    address: x.address,
+                    inner_name: x.inner_name.unwrap_or(default.inner_name),
+                })
+            }
+        })
+    }
+}
+
+impl From<&NetworkMode> for NetworkFields {
+    fn from(mode: &NetworkMode) -> Self {
+        let mut x = NetworkFields {
+            kind: NetworkKind::Loopback,
+            bridge: None,
+            subnet: None,
+            prefix_len: None,
+            gateway: None,
+            address: None,
+            inner_name: None,
+        };
+        match mode {
+            NetworkMode::Loopback => {}
+            NetworkMode::Host => x.kind = NetworkKind::Host,
+            NetworkMode::Veth(config) => {
+                x.kind = NetworkKind::Veth;
+                x.bridge = Some(config.bridge.clone());
+                x.subnet = Some(config.subnet);
+                x.prefix_len = Some(config.prefix_len);
+                x.gateway = config.gateway;
+                x.address = config.address;
+                x.inner_name = Some(config.inner_name.clone());
+            }
+        }
+        x
+    }
+}
+
+impl Serialize for NetworkMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        NetworkFields::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NetworkMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_checked::<D, NetworkFields, _>(deserializer)
+    }
+}
+
+// Resources are the keys of rlimits, and serde only keeps the path through
+// keys which are read as strings.
+const RESOURCE_NAMES: [&str; 15] = [
+    "address_space",
+    "core_size",
+    "cpu_time",
+    "data_size",
+    "file_size",
+    "locked_memory",
+    "message_queue",
+    "nice",
+    "open_files",
+    "pending_signals",
+    "processes",
+    "realtime_priority",
+    "realtime_time",
+    "resident_set",
+    "stack_size",
+];
+
+const RESOURCES: [Resource; 15] = [
+    Resource::AddressSpace,
+    Resource::CoreSize,
+    Resource::CpuTime,
+    Resource::DataSize,
+    Resource::FileSize,
+    Resource::LockedMemory,
+    Resource::MessageQueue,
+    Resource::Nice,
+    Resource::OpenFiles,
+    Resource::PendingSignals,
+    Resource::Processes,
+    Resource::RealtimePriority,
+    Resource::RealtimeTime,
+    Resource::ResidentSet,
+    Resource::StackSize,
+];
+
+impl Serialize for Resource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let index = RESOURCES.iter().position(|x| x == self).unwrap();
+        serializer.serialize_str(RESOURCE_NAMES[index])
+    }
+}
+
+impl<'de> Deserialize<'de> for Resource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match RESOURCE_NAMES.iter().position(|x| *x == name) {
+            Some(index) => Ok(RESOURCES[index]),
+            None => Err(de::Error::unknown_variant(&name, &RESOURCE_NAMES)),
+        }
+    }
+}
+
+impl Serialize for LimitSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            RlimitValue::Limited(x) => serializer.serialize_u64(x),
+            RlimitValue::Unlimited => serializer.serialize_str("unlimited"),
+        }
+    }
+}
+
+struct LimitVisitor;
+
+impl<'de> de::Visitor<'de> for LimitVisitor {
+    type Value = LimitSpec;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a non-negative number or \"unlimited\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<LimitSpec, E> {
+        Ok(LimitSpec(RlimitValue::Limited(value)))
+    }
+
+    // toml only knows signed integers
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<LimitSpec, E> {
+        if value < 0 {
+            return Err(E::invalid_value(de::Unexpected::Signed(value), &self));
+        }
+        self.visit_u64(value as u64)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<LimitSpec, E> {
+        match value {
+            "unlimited" => Ok(LimitSpec(RlimitValue::Unlimited)),
+            x => Err(E::invalid_value(de::Unexpected::Str(x), &self)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LimitSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(LimitVisitor)
+    }
+}
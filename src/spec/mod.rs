@@ -0,0 +1,313 @@
+use {
+    crate::{
+        container::{Config, Stdio},
+        filesystem::{self, MountNamespacedFs, RootMode},
+        idmap::IdMapping,
+        network::NetworkMode,
+        resource::CGroupLimitPolicy,
+        security::{self, ApplySecurityPolicy, Resource, Rlimit, RlimitPolicy, RlimitValue},
+    },
+    caps::Capability,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, fmt, os::unix::io::RawFd, time::Duration},
+};
+
+mod flat;
+
+// Where a spec is wrong: the path of the field, e.g. fs[1].size_limit, and
+// the position in the text if it is known, counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    field: String,
+    line: Option<usize>,
+    column: Option<usize>,
+    message: String,
+}
+
+impl ParseError {
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        self.column
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// A Config as it is written in a file. Every field may be left out and then
+// keeps the value of Config::default().
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigSpec {
+    pub uid: Option<u64>, // random if left out
+    pub working_path: Option<String>,
+    pub hostname: Option<String>,
+    pub target_executable: Option<String>,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub search_path: bool,
+    pub network: NetworkMode, // e.g. { type = "veth", bridge = "br0" }
+    pub time_namespace: bool,
+    pub fs: Vec<MountSpec>,
+    pub root_mode: RootMode,
+    pub security_policies: Option<Vec<PolicySpec>>, // None keeps the default policies
+    pub rlimits: BTreeMap<Resource, RlimitSpec>,    // e.g. open_files = { soft = 64 }
+    pub cgroup_limits: CGroupLimitPolicy,
+    pub inner_uid: u32,
+    pub inner_gid: u32,
+    pub id_mapping: Option<IdMapping>,
+    pub time_limit: Option<u64>,    // in milliseconds
+    pub setup_timeout: Option<u64>, // in milliseconds
+    pub stdin: StdioSpec,
+    pub stdout: StdioSpec,
+    pub stderr: StdioSpec,
+    pub extra_fds: Vec<(RawFd, RawFd)>,
+    pub output_limit: Option<u64>,
+}
+
+// An entry of fs, e.g. { type = "read_only_bind", source = "/srv/image" }.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(into = "flat::MountFields")]
+pub enum MountSpec {
+    Tmp,  // MountTmpFs
+    Proc, // MountProcFs
+    Bind {
+        source: String,
+    },
+    ReadOnlyBind {
+        source: String,
+    },
+    // an empty directory of the workspace for each inner if there is no
+    // source, at /mnt if there is no inner
+    Extra {
+        source: Option<String>,
+        inner: Option<String>,
+    },
+    SizedTmp {
+        size_limit: Option<u64>, // in bytes
+        target: Option<String>,  // /tmp by default
+    },
+}
+
+// An entry of security_policies, e.g. { type = "seccomp", deny = ["ptrace"] }.
+// Fields left out keep the value of the default policy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(into = "flat::PolicyFields")]
+pub enum PolicySpec {
+    Capability {
+        allow: Option<Vec<Capability>>, // e.g. "CAP_CHOWN"
+        deny: Option<Vec<Capability>>,
+    },
+    Seccomp {
+        allow: Option<Vec<String>>, // names of syscalls
+        deny: Option<Vec<String>>,
+    },
+}
+
+// Either a number or "unlimited".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitSpec(pub RlimitValue);
+
+// The hard limit is the soft one if it is left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RlimitSpec {
+    pub soft: LimitSpec,
+    pub hard: Option<LimitSpec>,
+}
+
+// e.g. { type = "capture", max_bytes = 4096 }
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+#[serde(into = "flat::StdioFields")]
+pub enum StdioSpec {
+    #[default]
+    Inherit,
+    Null,
+    File(String),
+    Fd(RawFd),
+    // stdin only, given as text
+    Bytes(String),
+    Capture {
+        max_bytes: u64,
+    },
+    Pty,
+}
+
+impl From<MountSpec> for Box<dyn MountNamespacedFs> {
+    fn from(spec: MountSpec) -> Self {
+        use filesystem::*;
+        match spec {
+            MountSpec::Tmp => box MountTmpFs,
+            MountSpec::Proc => box MountProcFs,
+            MountSpec::Bind { source } => box MountBindFs::from(source),
+            MountSpec::ReadOnlyBind { source } => box MountReadOnlyBindFs::from(source),
+            MountSpec::Extra { source, inner } => match (source, inner) {
+                (Some(source), Some(inner)) => box MountExtraFs::build(source, inner),
+                (Some(source), None) => box MountExtraFs::from(source),
+                (None, Some(inner)) => box MountExtraFs::new_at(inner),
+                (None, None) => box MountExtraFs::new(),
+            },
+            MountSpec::SizedTmp { size_limit, target } => match (size_limit, target) {
+                (size_limit, Some(target)) => box MountSizedTmpFs::build(size_limit, target),
+                (Some(size_limit), None) => box MountSizedTmpFs::from(size_limit),
+                (None, None) => box MountSizedTmpFs::default(),
+            },
+        }
+    }
+}
+
+impl From<PolicySpec> for Box<dyn ApplySecurityPolicy> {
+    fn from(spec: PolicySpec) -> Self {
+        match spec {
+            PolicySpec::Capability { allow, deny } => {
+                let default: security::CapabilityPolicy = Default::default();
+                box security::CapabilityPolicy {
+                    allow: allow.map_or(default.allow, |x| x.into_iter().collect()),
+                    deny: deny.map_or(default.deny, |x| x.into_iter().collect()),
+                }
+            }
+            PolicySpec::Seccomp { allow, deny } => {
+                let default: security::SeccompPolicy = Default::default();
+                box security::SeccompPolicy {
+                    allow: allow.unwrap_or(default.allow),
+                    deny: deny.unwrap_or(default.deny),
+                }
+            }
+        }
+    }
+}
+
+impl From<RlimitSpec> for Rlimit {
+    fn from(spec: RlimitSpec) -> Self {
+        Rlimit::new(spec.soft.0, spec.hard.unwrap_or(spec.soft).0)
+    }
+}
+
+impl From<StdioSpec> for Stdio {
+    fn from(spec: StdioSpec) -> Self {
+        match spec {
+            StdioSpec::Inherit => Stdio::Inherit,
+            StdioSpec::Null => Stdio::Null,
+            StdioSpec::File(x) => Stdio::File(x),
+            StdioSpec::Fd(x) => Stdio::Fd(x),
+            StdioSpec::Bytes(x) => Stdio::Bytes(x.into_bytes()),
+            StdioSpec::Capture { max_bytes } => Stdio::Capture { max_bytes },
+            StdioSpec::Pty => Stdio::Pty,
+        }
+    }
+}
+
+impl From<ConfigSpec> for Config {
+    fn from(spec: ConfigSpec) -> Self {
+        let mut config: Config = Default::default();
+        if let Some(x) = spec.uid {
+            config.uid = x;
+        }
+        if let Some(x) = spec.working_path {
+            config.working_path = x;
+        }
+        if let Some(x) = spec.hostname {
+            config.hostname = x;
+        }
+        if let Some(x) = spec.target_executable {
+            config.target_executable = x;
+        }
+        config.args = spec.args;
+        config.env = spec.env.into_iter().collect();
+        config.search_path = spec.search_path;
+        config.network = spec.network;
+        config.time_namespace = spec.time_namespace;
+        config.fs = spec.fs.into_iter().map(Into::into).collect();
+        config.root_mode = spec.root_mode;
+        if let Some(x) = spec.security_policies {
+            config.security_policies = x.into_iter().map(Into::into).collect();
+        }
+        let mut rlimits = RlimitPolicy::new();
+        for (resource, limit) in spec.rlimits.into_iter() {
+            rlimits.set(resource, limit.into());
+        }
+        config.rlimits = rlimits;
+        config.cgroup_limits = box spec.cgroup_limits;
+        config.inner_uid = spec.inner_uid;
+        config.inner_gid = spec.inner_gid;
+        config.id_mapping = spec.id_mapping;
+        if let Some(x) = spec.time_limit {
+            config.time_limit = Duration::from_millis(x);
+        }
+        if let Some(x) = spec.setup_timeout {
+            config.setup_timeout = Duration::from_millis(x);
+        }
+        config.stdin = spec.stdin.into();
+        config.stdout = spec.stdout.into();
+        config.stderr = spec.stderr.into();
+        config.extra_fds = spec.extra_fds;
+        config.output_limit = spec.output_limit;
+        config
+    }
+}
+
+impl ConfigSpec {
+    pub fn from_toml(text: &str) -> Result<Self, ParseError> {
+        serde_path_to_error::deserialize(toml::Deserializer::new(text)).map_err(|e| {
+            let (line, column) = match e.inner().span() {
+                Some(span) => position_of(text, span.start),
+                None => (None, None),
+            };
+            ParseError {
+                field: e.path().to_string(),
+                line,
+                column,
+                message: e.inner().message().to_string(),
+            }
+        })
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ParseError> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let spec = serde_path_to_error::deserialize(&mut deserializer)
+            .map_err(|e| json_error(e.path().to_string(), e.inner()))?;
+        // anything but whitespace after the spec
+        deserializer
+            .end()
+            .map_err(|e| json_error(".".to_string(), &e))?;
+        Ok(spec)
+    }
+}
+
+fn json_error(field: String, e: &serde_json::Error) -> ParseError {
+    // serde_json appends the position to the message, we keep it apart
+    let position = format!(" at line {} column {}", e.line(), e.column());
+    ParseError {
+        field,
+        line: Some(e.line()).filter(|x| *x > 0),
+        column: Some(e.column()).filter(|x| *x > 0),
+        message: e.to_string().trim_end_matches(&position).to_string(),
+    }
+}
+
+// The line and the column of a byte offset, counted from 1.
+fn position_of(text: &str, offset: usize) -> (Option<usize>, Option<usize>) {
+    let before = match text.get(..offset) {
+        Some(x) => x,
+        None => return (None, None),
+    };
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|x| *x != '\n').count() + 1;
+    (Some(line), Some(column))
+}
@@ -0,0 +1,208 @@
+use {
+    crate::container::{self, Container, EntryStage, ExitStatus, RunResult, TimeLimitKind},
+    crate::security::{Resource, Rlimit, RlimitValue},
+    nix::{errno::Errno, sys::signal::Signal},
+    std::time::Duration,
+};
+
+#[derive(Debug, Clone)]
+pub enum Verdict {
+    Ready(RunResult), // exited normally, the output is ready to be checked
+    WrongAnswer {
+        result: RunResult,
+    },
+    TimeLimitExceeded {
+        kind: TimeLimitKind,
+        limit: Duration,
+        result: RunResult,
+    },
+    OutputLimitExceeded {
+        limit: Option<u64>,
+        result: RunResult,
+    },
+    MemoryLimitExceeded {
+        limit: Option<i64>,
+        result: RunResult,
+    },
+    PidLimitExceeded {
+        limit: Option<u32>,
+        result: RunResult,
+    },
+    RuntimeError {
+        exit_code: Option<i32>,
+        signal: Option<&'static str>,
+        result: RunResult,
+    },
+    SystemError {
+        stage: Option<EntryStage>, // None if it is not a failure to start
+        errno: Option<Errno>,
+        message: String,
+    },
+}
+
+impl Verdict {
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            Verdict::Ready(_) => "OK",
+            Verdict::WrongAnswer { .. } => "WA",
+            Verdict::TimeLimitExceeded { .. } => "TLE",
+            Verdict::OutputLimitExceeded { .. } => "OLE",
+            Verdict::MemoryLimitExceeded { .. } => "MLE",
+            Verdict::PidLimitExceeded { .. } => "PLE",
+            Verdict::RuntimeError { .. } => "RE",
+            Verdict::SystemError { .. } => "SE",
+        }
+    }
+
+    pub fn result(&self) -> Option<&RunResult> {
+        match self {
+            Verdict::Ready(result)
+            | Verdict::WrongAnswer { result }
+            | Verdict::TimeLimitExceeded { result, .. }
+            | Verdict::OutputLimitExceeded { result, .. }
+            | Verdict::MemoryLimitExceeded { result, .. }
+            | Verdict::PidLimitExceeded { result, .. }
+            | Verdict::RuntimeError { result, .. } => Some(result),
+            Verdict::SystemError { .. } => None,
+        }
+    }
+}
+
+// A container which has failed to start is never the fault of the program.
+fn start_failure(container: &Container) -> Option<Verdict> {
+    if let Some(failure) = container.entry_failure() {
+        return Some(Verdict::SystemError {
+            stage: Some(failure.stage()),
+            errno: failure.errno(),
+            message: failure.message().to_string(),
+        });
+    }
+    let e = container.setup_error()?;
+    match e {
+        container::Error::SetupTimedOut(stage) | container::Error::ChildDied(stage) => {
+            Some(Verdict::SystemError {
+                stage: *stage,
+                errno: None,
+                message: e.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+// Returns None if the container has neither failed to start nor been waited.
+pub fn judge(container: &Container) -> Option<Verdict> {
+    if let Some(verdict) = start_failure(container) {
+        return Some(verdict);
+    }
+
+    let result = container.result()?.clone();
+    let config = container.config();
+
+    // The target is the init of its pid namespace, which ignores the SIGXCPU
+    // of the soft limit, so it is killed by SIGKILL at the hard limit. The cpu
+    // time of rusage may fall a little short of that, the soft one is checked.
+    let cpu_rlimit = match config.rlimits.get(Resource::CpuTime) {
+        Some(Rlimit {
+            soft: RlimitValue::Limited(x),
+            hard: RlimitValue::Limited(_),
+        }) => Some(Duration::from_secs(x)),
+        _ => None,
+    };
+    let killed_by_rlimit = match (result.status, cpu_rlimit) {
+        (ExitStatus::Signaled(Signal::SIGKILL), Some(x)) => result.cpu_time() >= x,
+        _ => false,
+    };
+    let time_limit_exceeded = match result.time_limit_exceeded {
+        None if killed_by_rlimit => Some(TimeLimitKind::Rlimit),
+        x => x,
+    };
+    if let Some(kind) = time_limit_exceeded {
+        return Some(Verdict::TimeLimitExceeded {
+            kind,
+            limit: match kind {
+                TimeLimitKind::Wall => config.time_limit,
+                TimeLimitKind::Cpu => config.cgroup_limits.time_limit().unwrap_or_default(),
+                TimeLimitKind::Rlimit => cpu_rlimit.unwrap_or_default(),
+            },
+            result,
+        });
+    }
+
+    if result.output_limit_exceeded {
+        return Some(Verdict::OutputLimitExceeded {
+            limit: config.output_limit,
+            result,
+        });
+    }
+
+    if result.oom_kills > 0 {
+        return Some(Verdict::MemoryLimitExceeded {
+            limit: config.cgroup_limits.memory_limit(),
+            result,
+        });
+    }
+
+    if result.status.success() {
+        return Some(Verdict::Ready(result));
+    }
+
+    // a refused fork only matters if the program could not cope with it
+    if result.refused_forks > 0 {
+        return Some(Verdict::PidLimitExceeded {
+            limit: config.cgroup_limits.fork_limit(),
+            result,
+        });
+    }
+
+    Some(Verdict::RuntimeError {
+        exit_code: result.status.code(),
+        signal: match result.status {
+            ExitStatus::Signaled(sig) => Some(sig.as_str()),
+            _ => None,
+        },
+        result,
+    })
+}
+
+// The solution is judged first for the limits it has exceeded itself. After
+// that a rejecting interactor means WA, whatever happened to the solution
+// afterwards, e.g. SIGPIPE when writing to the interactor which has exited.
+// An interactor exiting with one of failure_codes has failed itself, e.g.
+// testlib exits with 3 if the test data is broken.
+pub fn judge_interactive(
+    solution: &Container,
+    interactor: &Container,
+    failure_codes: &[i32],
+) -> Option<Verdict> {
+    let verdict = judge(solution)?;
+    match verdict {
+        Verdict::SystemError { .. }
+        | Verdict::TimeLimitExceeded { .. }
+        | Verdict::OutputLimitExceeded { .. }
+        | Verdict::MemoryLimitExceeded { .. } => return Some(verdict),
+        _ => {}
+    }
+
+    if let Some(verdict) = start_failure(interactor) {
+        return Some(verdict);
+    }
+
+    match interactor.result()?.status {
+        ExitStatus::Exited(0) => Some(verdict),
+        ExitStatus::Exited(code) if failure_codes.contains(&code) => Some(Verdict::SystemError {
+            stage: None,
+            errno: None,
+            message: format!("interactor failed with exit code {}", code),
+        }),
+        ExitStatus::Exited(_) => Some(Verdict::WrongAnswer {
+            result: verdict.result()?.clone(),
+        }),
+        // a crashing or hanging interactor is our fault
+        ExitStatus::Signaled(sig) => Some(Verdict::SystemError {
+            stage: None,
+            errno: None,
+            message: format!("interactor killed by {}", sig.as_str()),
+        }),
+    }
+}
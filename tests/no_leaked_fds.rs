@@ -0,0 +1,97 @@
+// Needs root, the host root is bound read-only as the root of the container:
+// cargo test --test no_leaked_fds -- --ignored
+
+#![feature(box_syntax)]
+
+use {
+    nix::{
+        errno::Errno,
+        fcntl::{self, OFlag},
+        sys::stat::Mode,
+        unistd,
+    },
+    ssandbox::{
+        container::{Config, Container, ExecSpec, Stdio},
+        filesystem,
+        security::SeccompPolicy,
+    },
+};
+
+// Neither opened with O_CLOEXEC nor given to the container.
+fn leak_fd() {
+    let leaked = fcntl::open("/etc/hostname", OFlag::O_RDONLY, Mode::empty()).unwrap();
+    unistd::dup2(leaked, 100).unwrap();
+    unistd::close(leaked).unwrap();
+}
+
+fn config() -> Config {
+    let mut config: Config = Default::default();
+    config.fs.push(box filesystem::MountReadOnlyBindFs::from("/".to_string()));
+    config.fs.push(box filesystem::MountProcFs);
+    // the fds are closed before seccomp is loaded
+    config.security_policies = vec![box SeccompPolicy {
+        allow: Vec::new(),
+        deny: vec!["close_range".to_string()],
+    }];
+    config
+}
+
+// 0, 1 and 2 plus the directory ls is reading
+fn open_fds(stdout: Option<Vec<u8>>) -> Vec<String> {
+    let output = String::from_utf8(stdout.unwrap()).unwrap();
+    output.split_whitespace().map(|x| x.to_string()).collect()
+}
+
+fn run_ls() -> Vec<String> {
+    let mut config = config();
+    config.target_executable = "/bin/ls".to_string();
+    config.args = vec!["/proc/self/fd".to_string()];
+    config.stdout = Stdio::Capture { max_bytes: 4096 };
+    let mut c = Container::from(config);
+    c.start().unwrap();
+    open_fds(c.wait().unwrap().stdout)
+}
+
+#[test]
+#[ignore = "needs root"]
+fn start_leaks_no_fd() {
+    leak_fd();
+    assert_eq!(run_ls(), vec!["0", "1", "2", "3"]);
+}
+
+// As on linux before 5.9, the container inherits the filter of this thread.
+#[test]
+#[ignore = "needs root"]
+fn start_leaks_no_fd_without_close_range() {
+    leak_fd();
+    std::thread::spawn(|| {
+        use libscmp::Action;
+        let mut filter = libscmp::Filter::new(Action::Allow).unwrap();
+        let close_range = libscmp::resolve_syscall_name("close_range").unwrap();
+        let enosys = Action::Errno(Errno::ENOSYS as i32);
+        filter.add_rule_exact(enosys, close_range, &[]).unwrap();
+        filter.load().unwrap();
+        assert_eq!(run_ls(), vec!["0", "1", "2", "3"]);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+#[ignore = "needs root"]
+fn exec_leaks_no_fd() {
+    leak_fd();
+
+    let mut config = config();
+    config.args = vec!["-c".to_string(), "sleep 5".to_string()];
+    config.time_limit = std::time::Duration::from_secs(5);
+    let mut c = Container::from(config);
+    c.start().unwrap();
+
+    let mut spec = ExecSpec::default();
+    spec.target_executable = "/bin/ls".to_string();
+    spec.args = vec!["/proc/self/fd".to_string()];
+    spec.stdout = Stdio::Capture { max_bytes: 4096 };
+    let result = c.exec(spec).unwrap().wait().unwrap();
+    assert_eq!(open_fds(result.stdout), vec!["0", "1", "2", "3"]);
+}
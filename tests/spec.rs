@@ -0,0 +1,267 @@
+// cargo test --test spec --features spec
+
+use {
+    ssandbox::{
+        container::{Config, Stdio},
+        idmap::IdRange,
+        network::NetworkMode,
+        security::{Resource, Rlimit, RlimitValue},
+        spec::{ConfigSpec, LimitSpec, ParseError},
+    },
+    std::{net::Ipv4Addr, time::Duration},
+};
+
+const TOML: &str = r#"
+uid = 7
+target_executable = "/bin/sh"
+args = ["-c", "echo $GREETING"]
+time_limit = 2000
+inner_uid = 1000
+inner_gid = 1000
+extra_fds = [[5, 3]]
+
+[env]
+GREETING = "hello"
+
+[network]
+type = "veth"
+bridge = "br0"
+address = "10.88.0.9"
+
+[[fs]]
+type = "read_only_bind"
+source = "/srv/image"
+
+[[fs]]
+type = "extra"
+inner = "/data"
+
+[[fs]]
+type = "sized_tmp"
+size_limit = 16777216
+
+[[security_policies]]
+type = "capability"
+allow = ["CAP_CHOWN", "CAP_KILL"]
+
+[[security_policies]]
+type = "seccomp"
+deny = ["ptrace"]
+
+[rlimits]
+open_files = { soft = 64 }
+core_size = { soft = 0, hard = "unlimited" }
+
+[cgroup_limits]
+memory_limit = 67108864
+fork_limit = 16
+
+[[id_mapping.uid]]
+inside = 1000
+outside = 100000
+count = 1
+
+[[id_mapping.gid]]
+inside = 1000
+outside = 100000
+count = 1
+
+[stdin]
+type = "bytes"
+data = "1 2\n"
+
+[stdout]
+type = "capture"
+max_bytes = 1024
+"#;
+
+const JSON: &str = r#"{
+    "uid": 7,
+    "target_executable": "/bin/sh",
+    "args": ["-c", "echo $GREETING"],
+    "time_limit": 2000,
+    "inner_uid": 1000,
+    "inner_gid": 1000,
+    "extra_fds": [[5, 3]],
+    "env": { "GREETING": "hello" },
+    "network": { "type": "veth", "bridge": "br0", "address": "10.88.0.9" },
+    "fs": [
+        { "type": "read_only_bind", "source": "/srv/image" },
+        { "type": "extra", "inner": "/data" },
+        { "type": "sized_tmp", "size_limit": 16777216 }
+    ],
+    "security_policies": [
+        { "type": "capability", "allow": ["CAP_CHOWN", "CAP_KILL"] },
+        { "type": "seccomp", "deny": ["ptrace"] }
+    ],
+    "rlimits": {
+        "open_files": { "soft": 64 },
+        "core_size": { "soft": 0, "hard": "unlimited" }
+    },
+    "cgroup_limits": { "memory_limit": 67108864, "fork_limit": 16 },
+    "id_mapping": {
+        "uid": [{ "inside": 1000, "outside": 100000, "count": 1 }],
+        "gid": [{ "inside": 1000, "outside": 100000, "count": 1 }]
+    },
+    "stdin": { "type": "bytes", "data": "1 2\n" },
+    "stdout": { "type": "capture", "max_bytes": 1024 }
+}"#;
+
+// ConfigSpec has no PartialEq, and neither has what Config is made of
+fn same(a: &ConfigSpec, b: &ConfigSpec) -> bool {
+    format!("{:?}", a) == format!("{:?}", b)
+}
+
+fn check_config(config: &Config) {
+    assert_eq!(config.uid, 7);
+    assert_eq!(config.target_executable, "/bin/sh");
+    assert_eq!(config.args, vec!["-c", "echo $GREETING"]);
+    assert_eq!(
+        config.env,
+        vec![("GREETING".to_string(), "hello".to_string())]
+    );
+    assert_eq!(config.time_limit, Duration::from_secs(2));
+    assert_eq!(config.setup_timeout, Config::default().setup_timeout);
+    assert_eq!((config.inner_uid, config.inner_gid), (1000, 1000));
+    assert_eq!(config.extra_fds, vec![(5, 3)]);
+    match &config.network {
+        NetworkMode::Veth(x) => {
+            assert_eq!(x.bridge, "br0");
+            assert_eq!(x.address, Some(Ipv4Addr::new(10, 88, 0, 9)));
+            assert_eq!(x.prefix_len, 16);
+        }
+        x => panic!("{:?}", x),
+    }
+    assert_eq!(config.fs.len(), 3);
+    assert_eq!(config.security_policies.len(), 2);
+    assert_eq!(
+        config.rlimits.get(Resource::OpenFiles),
+        Some(Rlimit::new(
+            RlimitValue::Limited(64),
+            RlimitValue::Limited(64)
+        ))
+    );
+    assert_eq!(
+        config.rlimits.get(Resource::CoreSize),
+        Some(Rlimit::new(RlimitValue::Limited(0), RlimitValue::Unlimited))
+    );
+    assert_eq!(config.rlimits.get(Resource::CpuTime), None);
+    assert_eq!(config.cgroup_limits.memory_limit(), Some(67108864));
+    assert_eq!(config.cgroup_limits.fork_limit(), Some(16));
+    let id_mapping = config.id_mapping.as_ref().unwrap();
+    assert_eq!(id_mapping.uid, vec![IdRange::new(1000, 100000, 1)]);
+    assert_eq!(id_mapping.gid, vec![IdRange::new(1000, 100000, 1)]);
+    assert_eq!(config.stdin, Stdio::Bytes(b"1 2\n".to_vec()));
+    assert_eq!(config.stdout, Stdio::Capture { max_bytes: 1024 });
+    assert_eq!(config.stderr, Stdio::Inherit);
+}
+
+#[test]
+fn toml_and_json_give_the_same_config() {
+    let from_toml = ConfigSpec::from_toml(TOML).unwrap();
+    let from_json = ConfigSpec::from_json(JSON).unwrap();
+    assert!(same(&from_toml, &from_json));
+    check_config(&from_toml.into());
+    check_config(&from_json.into());
+}
+
+#[test]
+fn spec_round_trips() {
+    let spec = ConfigSpec::from_toml(TOML).unwrap();
+    let again = ConfigSpec::from_toml(&toml::to_string(&spec).unwrap()).unwrap();
+    assert!(same(&spec, &again));
+    let again = ConfigSpec::from_json(&serde_json::to_string(&spec).unwrap()).unwrap();
+    assert!(same(&spec, &again));
+    check_config(&again.into());
+}
+
+fn error_at(e: ParseError, field: &str, line: usize, column: usize) -> String {
+    assert_eq!(e.field(), field, "{}", e);
+    assert_eq!((e.line(), e.column()), (Some(line), Some(column)), "{}", e);
+    e.message().to_string()
+}
+
+#[test]
+fn unknown_field_of_an_fs_entry() {
+    let text = "[[fs]]\ntype = \"proc\"\n\n[[fs]]\ntype = \"sized_tmp\"\nsize = 1024\n";
+    let e = ConfigSpec::from_toml(text).unwrap_err();
+    assert!(error_at(e, "fs[1].size", 6, 1).starts_with("unknown field `size`"));
+
+    let text = "{\n  \"fs\": [\n    { \"type\": \"proc\" },\n    { \"type\": \"sized_tmp\", \"size\": 1024 }\n  ]\n}";
+    let e = ConfigSpec::from_json(text).unwrap_err();
+    assert!(error_at(e, "fs[1].size", 4, 33).starts_with("unknown field `size`"));
+}
+
+// The type of an entry may come after its fields, so the entry is pointed at.
+#[test]
+fn field_of_another_type() {
+    let text = "[[fs]]\ntype = \"tmp\"\n\n[[fs]]\ntype = \"proc\"\nsource = \"/srv\"\n";
+    let e = ConfigSpec::from_toml(text).unwrap_err();
+    assert_eq!(
+        error_at(e, "fs[1]", 4, 1),
+        "field `source` does not apply to this type"
+    );
+
+    let text = "{ \"fs\": [{ \"type\": \"proc\", \"source\": \"/srv\" }] }";
+    let e = ConfigSpec::from_json(text).unwrap_err();
+    assert_eq!(
+        error_at(e, "fs[0]", 1, 45),
+        "field `source` does not apply to this type"
+    );
+}
+
+#[test]
+fn unknown_capability() {
+    let text = "[[security_policies]]\ntype = \"seccomp\"\n\n\
+                [[security_policies]]\ntype = \"capability\"\nallow = [\"CAP_NOPE\"]\n";
+    let e = ConfigSpec::from_toml(text).unwrap_err();
+    assert_eq!(
+        error_at(e, "security_policies[1]", 4, 1),
+        "unknown capability `CAP_NOPE`"
+    );
+}
+
+#[test]
+fn unknown_rlimit() {
+    let text = "[rlimits]\nopen_files = { soft = 64 }\nopen_filez = { soft = 64 }\n";
+    let e = ConfigSpec::from_toml(text).unwrap_err();
+    assert!(error_at(e, "rlimits.open_filez", 3, 1).starts_with("unknown variant `open_filez`"));
+
+    let text = "{ \"rlimits\": { \"open_filez\": { \"soft\": 64 } } }";
+    let e = ConfigSpec::from_json(text).unwrap_err();
+    assert!(error_at(e, "rlimits.open_filez", 1, 27).starts_with("unknown variant `open_filez`"));
+}
+
+#[test]
+fn unlimited() {
+    let limit: LimitSpec = serde_json::from_str("\"unlimited\"").unwrap();
+    assert_eq!(limit, LimitSpec(RlimitValue::Unlimited));
+    let limit: LimitSpec = serde_json::from_str("64").unwrap();
+    assert_eq!(limit, LimitSpec(RlimitValue::Limited(64)));
+
+    let text = "[rlimits]\nstack_size = { soft = \"unlimited\" }\n";
+    let config: Config = ConfigSpec::from_toml(text).unwrap().into();
+    assert_eq!(
+        config.rlimits.get(Resource::StackSize),
+        Some(Rlimit::fixed(RlimitValue::Unlimited))
+    );
+
+    let text = "[rlimits]\nopen_files = { soft = \"lots\" }\n";
+    let e = ConfigSpec::from_toml(text).unwrap_err();
+    assert!(error_at(e, "rlimits.open_files.soft", 2, 23).starts_with("invalid value: string"));
+}
+
+#[test]
+fn negative_limit() {
+    let text = "[rlimits]\nopen_files = { soft = -1 }\n";
+    let e = ConfigSpec::from_toml(text).unwrap_err();
+    assert!(
+        error_at(e, "rlimits.open_files.soft", 2, 23).starts_with("invalid value: integer `-1`")
+    );
+
+    let text = "{ \"rlimits\": { \"open_files\": { \"soft\": -1 } } }";
+    let e = ConfigSpec::from_json(text).unwrap_err();
+    assert!(
+        error_at(e, "rlimits.open_files.soft", 1, 41).starts_with("invalid value: integer `-1`")
+    );
+}